@@ -1,15 +1,26 @@
 (() => {
     "use strict";
 
+    // on /w/<wall>/ everything goes to that wall, anywhere else to the default one
+    const WALL = location.pathname.match(/^\/w\/([^/]+)/)?.[1] ?? null;
+    const BASE = WALL ? `/w/${WALL}` : "";
     const API = Object.freeze({
-        FETCH: "/get_msgs",
-        POST: "/send_msg",
-        LAST: "/last_msg",
+        FETCH: `${BASE}/get_msgs`,
+        POST: `${BASE}/send_msg`,
+        DELETE: `${BASE}/delete_msg`,
+        EDIT: `${BASE}/edit_msg`,
+        REACT: `${BASE}/react`,
+        PINNED: `${BASE}/pinned`,
+        WS: `${BASE}/ws`,
         GIT_INFO: "/git_info"
     });
-    const CHAR_LIMIT    = 250;
-    const PAGE_SIZE     = 20;
-    const POLL_INTERVAL = 5_000;
+    const CHAR_LIMIT      = 250;
+    const PAGE_SIZE       = 20;
+    const RECONNECT_DELAY = 3_000;
+    const TOKENS_KEY      = "wall.delete_tokens";
+    const REACTED_KEY     = "wall.reactions";
+    // same as the server allows
+    const REACTIONS       = ["👍", "👎", "❤️", "😂", "😮", "😢", "🔥"];
 
     const qs   = obj => Object.entries(obj)
         .filter(([,v]) => v !== null && v !== undefined)
@@ -32,30 +43,82 @@
         .replace(/"/g, "&quot;")
         .replace(/'/g, "&#039;");
 
+    // delete tokens of messages sent from this browser, by message id
+    class Tokens {
+        static #all() {
+            try { return JSON.parse(localStorage.getItem(TOKENS_KEY)) ?? {}; }
+            catch { return {}; }
+        }
+        static get(id) { return Tokens.#all()[id] ?? null; }
+        static set(id, token) {
+            localStorage.setItem(TOKENS_KEY, JSON.stringify({ ...Tokens.#all(), [id]: token }));
+        }
+        static forget(id) {
+            const { [id]: _, ...rest } = Tokens.#all();
+            localStorage.setItem(TOKENS_KEY, JSON.stringify(rest));
+        }
+    }
+
+    // reactions given from this browser, by message id
+    class Reacted {
+        static #all() {
+            try { return JSON.parse(localStorage.getItem(REACTED_KEY)) ?? {}; }
+            catch { return {}; }
+        }
+        static has(id, emoji) { return (Reacted.#all()[id] ?? []).includes(emoji); }
+        static set(id, emoji, on) {
+            const all = Reacted.#all();
+            const rest = (all[id] ?? []).filter(e => e !== emoji);
+            all[id] = on ? [...rest, emoji] : rest;
+            if (!all[id].length) delete all[id];
+            localStorage.setItem(REACTED_KEY, JSON.stringify(all));
+        }
+    }
+
     class ChatAPI {
-        static async fetchBatch({ after = null, before = null, limit = PAGE_SIZE } = {}) {
-            const url = `${API.FETCH}?${qs({ after, before, limit })}`;
+        // { items (newest first), next_cursor, prev_cursor, has_more }
+        static async fetchPage({ cursor = null, limit = PAGE_SIZE } = {}) {
+            const url = `${API.FETCH}?${qs({ cursor, limit })}`;
             const res = await fetch(url);
             if (!res.ok) throw new Error("Не могу загрузить сообщения");
             return res.json();
         }
-        static async postMessage(body) {
-            const res = await fetch(API.POST, {
+        // pinned right now, newest first
+        static async fetchPinned() {
+            const res = await fetch(API.PINNED);
+            if (!res.ok) throw new Error("Не могу загрузить закреплённые");
+            return res.json();
+        }
+        static async #post(url, body, fallbackErr) {
+            const res = await fetch(url, {
                 method : "POST",
                 headers: { "Content-Type": "application/json" },
                 body   : JSON.stringify(body),
             });
             if (!res.ok) {
-                const { error } = await res.json();
-                throw new Error(error || "Ошибка отправки");
+                const { error, err } = await res.json();
+                throw new Error(error || err || fallbackErr);
             }
             return res.json();
         }
-        static async lastId() {
-            const res = await fetch(API.LAST);
-            if (!res.ok) throw new Error("Не могу проверить новые сообщения");
-            const { id } = await res.json();
-            return id;
+        static postMessage(body) {
+            return ChatAPI.#post(API.POST, body, "Ошибка отправки");
+        }
+        static deleteMessage(id, token) {
+            return ChatAPI.#post(API.DELETE, { id, token }, "Не могу удалить сообщение");
+        }
+        static editMessage(id, token, content) {
+            return ChatAPI.#post(API.EDIT, { id, token, content }, "Не могу изменить сообщение");
+        }
+        static react(id, emoji, remove) {
+            return ChatAPI.#post(API.REACT, { id, emoji, remove }, "Не могу поставить реакцию");
+        }
+        static subscribe(after, onUpdate, onClose) {
+            const proto = location.protocol === "https:" ? "wss:" : "ws:";
+            const ws = new WebSocket(`${proto}//${location.host}${API.WS}?${qs({ after })}`);
+            ws.addEventListener("message", e => onUpdate(JSON.parse(e.data)));
+            ws.addEventListener("close", onClose);
+            return ws;
         }
     }
 
@@ -132,11 +195,38 @@
                 }
             }, { once: true });
         }
-    }    class Renderer {
+    }    // announcements kept above the feed
+    class PinnedBar {
+        #el; #onClick;
+
+        constructor(el, onClick) {
+            this.#el = el;
+            this.#onClick = onClick;
+        }
+
+        render(msgs) {
+            this.#el.hidden = msgs.length === 0;
+            this.#el.replaceChildren(...msgs.map(({ id, author, content }) => {
+                const div = document.createElement("div");
+                div.className = "pinned-msg";
+                div.innerHTML = `📌 <b>${escapeHtml(author)}</b>: ${escapeHtml(content)}`;
+                div.addEventListener("click", () => this.#onClick(id));
+                return div;
+            }));
+        }
+    }
+
+    class Renderer {
         #list;
+        #msgs = new Map();
+        #onDelete; #onEdit; #onReply; #onReact;
         
-        constructor(listEl) { 
+        constructor(listEl, { onDelete, onEdit, onReply, onReact }) { 
             this.#list = listEl;
+            this.#onDelete = onDelete;
+            this.#onEdit = onEdit;
+            this.#onReply = onReply;
+            this.#onReact = onReact;
             const messages = Array.from(this.#list.children);
             messages.forEach(msg => {
                 if (!msg.parentElement.classList.contains('message-wrapper')) {
@@ -149,47 +239,141 @@
         }
         
         prepend(msg) {
-            const el = this.#tpl(msg);
-            const wrapper = document.createElement('div');
-            wrapper.className = 'message-wrapper';
-            wrapper.appendChild(el);
-            this.#list.prepend(wrapper);
+            this.#list.prepend(this.#wrap(msg));
         }
         
         append(msg) {
-            const el = this.#tpl(msg);
+            this.#list.append(this.#wrap(msg));
+        }
+
+        remove(id) {
+            this.#msgs.delete(id);
+            this.#find(id)?.remove();
+        }
+
+        replace(msg) {
+            if (!this.#msgs.has(msg.id)) return;
+            this.#msgs.set(msg.id, msg);
+            this.#find(msg.id)?.replaceChildren(this.#tpl(msg));
+        }
+
+        setReactions(id, reactions) {
+            const msg = this.#msgs.get(id);
+            if (msg) this.replace({ ...msg, reactions });
+        }
+
+        // redraw, e.g. once its delete token is known
+        refresh(id) {
+            const msg = this.#msgs.get(id);
+            if (msg) this.replace(msg);
+        }
+
+        scrollTo(id) {
+            this.#find(id)?.scrollIntoView({ behavior: "smooth", block: "center" });
+        }
+
+        #find(id) {
+            return this.#list.querySelector(`.message-wrapper[data-id="${id}"]`);
+        }
+
+        #wrap(msg) {
             const wrapper = document.createElement('div');
             wrapper.className = 'message-wrapper';
-            wrapper.appendChild(el);
-            this.#list.append(wrapper);
+            wrapper.dataset.id = msg.id;
+            this.#msgs.set(msg.id, msg);
+            wrapper.appendChild(this.#tpl(msg));
+            return wrapper;
         }
 
-        #tpl({ id, author, content, timestamp }) {
+        #reactionsTpl({ id, reactions = {} }) {
+            const row = document.createElement("div");
+            row.className = "reactions";
+            const chip = (emoji, count) => {
+                const button = document.createElement("button");
+                button.type = "button";
+                button.className = Reacted.has(id, emoji) ? "mine" : "";
+                button.textContent = count ? `${emoji} ${count}` : emoji;
+                button.addEventListener("click", () => this.#onReact(id, emoji));
+                return button;
+            };
+            Object.entries(reactions).forEach(([emoji, count]) => row.append(chip(emoji, count)));
+
+            const picker = document.createElement("div");
+            picker.className = "picker";
+            picker.hidden = true;
+            REACTIONS.filter(emoji => !reactions[emoji]).forEach(emoji => picker.append(chip(emoji, 0)));
+            const more = document.createElement("button");
+            more.type = "button";
+            more.className = "more";
+            more.textContent = "+";
+            more.addEventListener("click", () => picker.hidden = !picker.hidden);
+            if (picker.childElementCount) row.append(more, picker);
+            return row;
+        }
+
+        #tpl(msg) {
+            const { id, author, content, timestamp, edited_at, reply_to, expires_at } = msg;
             const div = document.createElement("div");
             div.className = "message";
             div.innerHTML = `
-        <div class="head">${escapeHtml(author)}</div>
+        <div class="head">${escapeHtml(author)}${reply_to ? ` <a class="reply-to" href="#">↩ #${reply_to}</a>` : ""}</div>
         <div class="body">${escapeHtml(content)}</div>
-        <time datetime="${timestamp}">${fmtDate(timestamp)}</time>
+        <time datetime="${timestamp}">${fmtDate(timestamp)}${edited_at ? " (изменено)" : ""}${expires_at ? ` · ⏳ исчезнет ${fmtDate(expires_at)}` : ""}</time>
+      `;
+            div.querySelector(".reply-to")?.addEventListener("click", e => {
+                e.preventDefault();
+                this.scrollTo(reply_to);
+            });
+
+            const actions = document.createElement("div");
+            actions.className = "actions";
+            actions.innerHTML = `
+        <button type="button" class="reply">Ответить</button>
       `;
+            actions.querySelector(".reply").addEventListener("click", () => this.#onReply(msg));
+            div.append(this.#reactionsTpl(msg));
+            if (Tokens.get(id)) {
+                actions.insertAdjacentHTML("beforeend", `
+        <button type="button" class="edit">Изменить</button>
+        <button type="button" class="delete">Удалить</button>
+      `);
+                actions.querySelector(".edit").addEventListener("click", () => this.#onEdit(msg));
+                actions.querySelector(".delete").addEventListener("click", () => this.#onDelete(msg));
+            }
+            div.append(actions);
             return div;
         }
     }
 
     /* ========= App ========= */
     class ChatApp {
-        #state     = { newest: null, oldest: null };
-        #renderer; #toast;
-        #author; #content; #counter;
+        // newest: id of the newest shown message, newer/older: cursors of the pages around them
+        #state     = { newest: null, newer: null, older: null };
+        #replyTo   = null;
+        #renderer; #toast; #pinned; #pinTimer;
+        #author; #content; #counter; #replying; #ttl;
         #observer;
 
         constructor() {
             const $ = id => document.getElementById(id);
-            this.#renderer = new Renderer($("messages"));
+            this.#renderer = new Renderer($("messages"), {
+                onDelete: msg => this.#onDelete(msg),
+                onEdit: msg => this.#onEdit(msg),
+                onReply: msg => this.#onReply(msg),
+                onReact: (id, emoji) => this.#onReact(id, emoji),
+            });
+            this.#pinned = new PinnedBar($("pinned"), id => this.#renderer.scrollTo(id));
             this.#toast = new Toast(document.querySelector(".toast-container"), () => this.#fetchNewest());
             this.#author = $("author");
             this.#content = $("content");
             this.#counter = $("counter");
+            this.#replying = $("replying");
+            this.#ttl = $("ttl");
+            this.#replying.addEventListener("click", () => this.#setReplyTo(null));
+            if (WALL) {
+                document.querySelector("h1").textContent = `Wall · ${WALL}`;
+                document.title = `${WALL} · ${document.title}`;
+            }
 
             // auto-resize for textarea
             const autoResize = () => {
@@ -211,8 +395,8 @@
         }
 
         async #boot() {
-            await this.#fetchNewest();
-            setInterval(() => this.#pollLast(), POLL_INTERVAL);
+            await Promise.all([this.#fetchNewest(), this.#fetchPinned()]);
+            this.#subscribe();
         }
 
         /* ----- Handlers ----- */
@@ -220,31 +404,70 @@
             e.preventDefault();
             const author  = this.#author.value.trim();
             const content = this.#content.value.trim();
+            const ttl     = Number(this.#ttl.value) || null;
             if (!author || !content) return;
 
             try {
-                await ChatAPI.postMessage({ author, content });
+                const { id, delete_token } = await ChatAPI.postMessage({ author, content, reply_to: this.#replyTo, ttl });
+                Tokens.set(id, delete_token);
+                this.#renderer.refresh(id);
+                this.#setReplyTo(null);
                 this.#content.value = "";
                 this.#content.style.height = "auto";
                 this.#updateCounter();
-                await this.#fetchNewest();
             } catch (err) { this.#toast.show(err.message, true); }
         }
 
-        async #onIntersect(entry) {
-            if (!entry.isIntersecting || this.#state.oldest === null) return;
+        async #onDelete({ id }) {
+            if (!confirm("Удалить сообщение?")) return;
+            try {
+                await ChatAPI.deleteMessage(id, Tokens.get(id));
+                Tokens.forget(id);
+            } catch (err) { this.#toast.show(err.message, true); }
+        }
+
+        async #onEdit({ id, content }) {
+            const edited = prompt("Новый текст", content)?.trim();
+            if (!edited || edited === content) return;
+            try {
+                await ChatAPI.editMessage(id, Tokens.get(id), edited);
+            } catch (err) { this.#toast.show(err.message, true); }
+        }
+
+        async #onReact(id, emoji) {
+            const remove = Reacted.has(id, emoji);
             try {
-                const msgs = await ChatAPI.fetchBatch({ before: this.#state.oldest });
-                if (!msgs.length) return;
-
-                msgs.sort((a, b) => b.id - a.id)        // DESC
-                    .forEach(m => {
-                        this.#renderer.append(m);
-                        this.#state.oldest = m.id;
-                    });
+                const { reactions } = await ChatAPI.react(id, emoji, remove);
+                Reacted.set(id, emoji, !remove);
+                this.#renderer.setReactions(id, reactions);
             } catch (err) { this.#toast.show(err.message, true); }
         }
 
+        #onReply({ id }) {
+            this.#setReplyTo(id);
+            this.#content.focus();
+        }
+
+        #setReplyTo(id) {
+            this.#replyTo = id;
+            this.#replying.hidden = id === null;
+            this.#replying.textContent = id === null ? "" : `↩ #${id} ✕`;
+        }
+
+        async #onIntersect(entry) {
+            const cursor = this.#state.older;
+            if (!entry.isIntersecting || cursor === null) return;
+            this.#state.older = null;   // one request per page
+            try {
+                const { items, next_cursor } = await ChatAPI.fetchPage({ cursor });
+                items.forEach(m => this.#renderer.append(m));
+                this.#state.older = next_cursor;
+            } catch (err) {
+                this.#state.older = cursor;
+                this.#toast.show(err.message, true);
+            }
+        }
+
         /* ----- Misc ----- */
         #updateCounter() {
             const left = CHAR_LIMIT - this.#content.value.length;
@@ -252,27 +475,61 @@
         }
 
         async #fetchNewest() {
+            const first = this.#state.newer === null;
             try {
-                const msgs = await ChatAPI.fetchBatch({ after: this.#state.newest ?? 0 });
-                msgs.sort((a, b) => a.id - b.id)        // ASC
-                    .forEach(m => this.#renderer.prepend(m));
+                let more;
+                do {
+                    const { items, next_cursor, prev_cursor, has_more } = await ChatAPI.fetchPage({ cursor: this.#state.newer });
+                    items.filter(m => m.id > (this.#state.newest ?? 0))
+                        .reverse()                           // oldest first, so the newest ends up on top
+                        .forEach(m => this.#renderer.prepend(m));
 
-                if (msgs.length) {
-                    this.#state.newest = Math.max(this.#state.newest ?? 0, msgs[msgs.length - 1].id);
-                    this.#state.oldest ??= msgs[0].id;
-                }
+                    // known from here on, even if the wall is empty
+                    this.#state.newest = Math.max(this.#state.newest ?? 0, items[0]?.id ?? 0);
+                    this.#state.newer = prev_cursor;
+                    if (first) this.#state.older = next_cursor;
+                    // the first page goes back in time, later ones forward until caught up
+                    more = !first && has_more;
+                } while (more);
             } catch (err) { this.#toast.show(err.message, true); }
-        }        async #pollLast() {
+        }
+
+        async #fetchPinned() {
+            clearTimeout(this.#pinTimer);
             try {
-                const lastId = await ChatAPI.lastId();
-                if (lastId > (this.#state.newest ?? 0)) {
-                    const diff = lastId - (this.#state.newest ?? 0);
-                    const message = diff === 1 
-                        ? "Новое сообщение! Кликни, чтобы обновить."
-                        : `${diff} новых сообщений! Кликни, чтобы обновить.`;
-                    this.#toast.show(message);
+                const msgs = await ChatAPI.fetchPinned();
+                this.#pinned.render(msgs);
+                // look again once the first pin runs out
+                const until = Math.min(...msgs.map(m => m.pinned_until ?? Infinity));
+                if (until !== Infinity) {
+                    const delay = Math.min(until * 1000 - Date.now() + 1000, 2 ** 31 - 1);
+                    this.#pinTimer = setTimeout(() => this.#fetchPinned(), delay);
                 }
-            } catch {/* silent */ }
+            } catch (err) { this.#toast.show(err.message, true); }
+        }
+
+        #subscribe() {
+            ChatAPI.subscribe(
+                this.#state.newest,                      // unknown sends no after, nothing to catch up on
+                update => this.#onUpdate(update),
+                () => setTimeout(() => this.#subscribe(), RECONNECT_DELAY),
+            );
+        }
+
+        #onUpdate({ type, ...msg }) {
+            // fell too far behind to be caught up, start over
+            if (type === "reload") return location.reload();
+            // pins only change through edits, and go away with what they pin
+            if (type === "edit" || type === "delete") this.#fetchPinned();
+            if (type === "delete") {
+                if (msg.id === this.#replyTo) this.#setReplyTo(null);
+                return this.#renderer.remove(msg.id);
+            }
+            if (type === "edit") return this.#renderer.replace(msg);
+            if (type === "reactions") return this.#renderer.setReactions(msg.id, msg.reactions);
+            if (msg.id <= (this.#state.newest ?? 0)) return;
+            this.#renderer.prepend(msg);
+            this.#state.newest = msg.id;
         }
     }
 
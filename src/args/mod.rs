@@ -1,26 +1,358 @@
+use anyhow::{anyhow, bail};
+use serde::Deserialize;
+use crate::database::{PoolConfig, DEFAULT_WALL};
+use crate::database::dump::Format;
+use crate::database::retention::Retention;
+use crate::integration::DispatcherConfig;
 
 #[derive(Debug)]
+pub enum Command {
+    Serve,
+    Migrate,
+    /// `export <path> [--wall <name>] [--format jsonl|csv]`
+    Export { path: String, wall: Option<String>, format: Format },
+    /// `import <path> [--format jsonl|csv]`
+    Import { path: String, format: Format },
+}
+
+/// The dump file and options following `export` or `import`,
+/// the format goes by the file's extension unless given.
+fn parse_dump_args(mut args: impl Iterator<Item = String>, takes_wall: bool) -> anyhow::Result<(String, Option<String>, Format)> {
+    let mut path = None;
+    let mut wall = None;
+    let mut format = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| anyhow!("{} needs a value", arg));
+        match arg.as_str() {
+            "--wall" if takes_wall => wall = Some(value()?),
+            "--format" => format = Some(Format::parse(&value()?)?),
+            _ if arg.starts_with("--") => bail!("Unknown option: {}", arg),
+            _ if path.is_none() => path = Some(arg),
+            _ => bail!("Unexpected argument: {}", arg),
+        }
+    }
+    let path = path.ok_or_else(|| anyhow!("Missing dump file"))?;
+    let format = format.unwrap_or_else(|| Format::of_path(&path));
+    Ok((path, wall, format))
+}
+
+/// One wall served by this process, as listed in the `WALLS_CONFIG` file.
+#[derive(Clone, Deserialize)]
+pub struct WallConfig {
+    /// shows up in its routes, `/w/<name>/...`
+    pub name: String,
+    /// where its messages are forwarded, none keeps them off Telegram
+    #[serde(default)]
+    pub tg_chat_id: Option<String>,
+    /// messages one ip may send per `rate_window_secs`
+    #[serde(default = "default_rate_limit")]
+    pub rate_limit: usize,
+    #[serde(default = "default_rate_window_secs")]
+    pub rate_window_secs: u64,
+    /// longest a message may ask to live before it is removed, 0 allows no ephemeral ones
+    #[serde(default = "default_max_ttl_secs")]
+    pub max_ttl_secs: u64,
+    /// where new messages are POSTed as signed JSON
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    /// URL of the Discord webhook messages are forwarded to
+    #[serde(default)]
+    pub discord_webhook: Option<String>,
+    /// Matrix room messages are mirrored into, with the account of `MATRIX_ACCESS_TOKEN`
+    #[serde(default)]
+    pub matrix_room_id: Option<String>,
+}
+
+// the Discord webhook URL is all it takes to post there, so it is kept out of logs
+impl std::fmt::Debug for WallConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WallConfig")
+            .field("name", &self.name)
+            .field("tg_chat_id", &self.tg_chat_id)
+            .field("rate_limit", &self.rate_limit)
+            .field("rate_window_secs", &self.rate_window_secs)
+            .field("max_ttl_secs", &self.max_ttl_secs)
+            .field("webhooks", &self.webhooks)
+            .field("discord_webhook", &self.discord_webhook.as_ref().map(|_| REDACTED))
+            .field("matrix_room_id", &self.matrix_room_id)
+            .finish()
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// shared with the receiver, which checks the `X-Wall-Signature` with it
+    pub secret: String,
+}
+
+impl std::fmt::Debug for WebhookConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebhookConfig")
+            .field("url", &self.url)
+            .field("secret", &REDACTED)
+            .finish()
+    }
+}
+
+fn default_rate_limit() -> usize {
+    2
+}
+
+fn default_rate_window_secs() -> u64 {
+    60
+}
+
+fn default_max_ttl_secs() -> u64 {
+    24 * 60 * 60
+}
+
+// sqlite's file when it is compiled in, as it always was, memory only without it
+#[cfg(feature = "sqlite_db")]
+const DEFAULT_DATABASE_URL: &str = "sqlite://db.sqlite";
+#[cfg(not(feature = "sqlite_db"))]
+const DEFAULT_DATABASE_URL: &str = "memory://";
+
 pub struct Args {
+    pub command: Command,
     pub port: u16,
-    #[cfg(feature = "sqlite_db")]
-    pub filename: String,
+    pub database_url: String,
+    pub db_pool: PoolConfig,
     pub repo_url: String,
     pub tg_token: String,
-    pub tg_chat_id: String,
+    pub admin_token: Option<String>,
+    pub admin_audit_log: String,
+    /// the default wall first, then the configured ones
+    pub walls: Vec<WallConfig>,
+    /// none keeps every message forever
+    pub retention: Option<Retention>,
+    /// how often expired ephemeral messages are removed
+    pub sweep_interval_secs: u64,
+    pub dispatcher: DispatcherConfig,
+    /// needed by walls with a `matrix_room_id`
+    pub matrix: Option<MatrixConfig>,
+}
+
+// shown in place of secrets when the config is logged
+const REDACTED: &str = "<redacted>";
+
+/// `url` with the password in it, if any, redacted.
+fn without_password(url: &str) -> String {
+    let Some((_, rest)) = url.split_once("://") else {
+        return url.to_string();
+    };
+    let authority = rest.split('/').next().unwrap_or_default();
+    match authority.rsplit_once('@').and_then(|(user_info, _)| user_info.split_once(':')) {
+        Some((user, password)) => url.replacen(&format!("{}:{}@", user, password), &format!("{}:{}@", user, REDACTED), 1),
+        None => url.to_string(),
+    }
+}
+
+// by hand, it gets logged on startup and must not show any secrets
+impl std::fmt::Debug for Args {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Args")
+            .field("command", &self.command)
+            .field("port", &self.port)
+            .field("database_url", &without_password(&self.database_url))
+            .field("db_pool", &self.db_pool)
+            .field("repo_url", &self.repo_url)
+            .field("tg_token", &REDACTED)
+            .field("admin_token", &self.admin_token.as_ref().map(|_| REDACTED))
+            .field("admin_audit_log", &self.admin_audit_log)
+            .field("walls", &self.walls)
+            .field("retention", &self.retention)
+            .field("sweep_interval_secs", &self.sweep_interval_secs)
+            .field("dispatcher", &self.dispatcher)
+            .field("matrix", &self.matrix)
+            .finish()
+    }
+}
+
+pub struct MatrixConfig {
+    /// base URL, e.g. `https://matrix.org`
+    pub homeserver: String,
+    pub access_token: String,
+}
+
+impl std::fmt::Debug for MatrixConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MatrixConfig")
+            .field("homeserver", &self.homeserver)
+            .field("access_token", &REDACTED)
+            .finish()
+    }
+}
+
+/// The Matrix account if `MATRIX_HOMESERVER` and `MATRIX_ACCESS_TOKEN` are both set.
+fn parse_matrix() -> anyhow::Result<Option<MatrixConfig>> {
+    let homeserver = std::env::var("MATRIX_HOMESERVER").ok().filter(|url| !url.is_empty());
+    let access_token = std::env::var("MATRIX_ACCESS_TOKEN").ok().filter(|token| !token.is_empty());
+    match (homeserver, access_token) {
+        (Some(homeserver), Some(access_token)) => Ok(Some(MatrixConfig { homeserver, access_token })),
+        (None, None) => Ok(None),
+        _ => bail!("MATRIX_HOMESERVER and MATRIX_ACCESS_TOKEN go together"),
+    }
+}
+
+/// A retention policy if `RETENTION_MAX_AGE` (seconds) or `RETENTION_MAX_MSGS` is set.
+fn parse_retention() -> anyhow::Result<Option<Retention>> {
+    let max_age_secs = std::env::var("RETENTION_MAX_AGE").ok().map(|age| age.parse()).transpose()?;
+    let max_msgs = std::env::var("RETENTION_MAX_MSGS").ok().map(|count| count.parse()).transpose()?;
+    if max_age_secs.is_none() && max_msgs.is_none() {
+        return Ok(None);
+    }
+    let interval_secs = std::env::var("RETENTION_INTERVAL")
+        .unwrap_or("3600".to_string())
+        .parse()?;
+    if max_age_secs == Some(0) || max_msgs == Some(0) || interval_secs == 0 {
+        bail!("Retention limits and interval have to be above 0");
+    }
+    Ok(Some(Retention {
+        max_age_secs,
+        max_msgs,
+        archive: std::env::var("RETENTION_ARCHIVE").ok().filter(|path| !path.is_empty()),
+        interval_secs,
+    }))
+}
+
+/// The default wall, forwarded to `TG_CHAT_ID` unless the config says otherwise,
+/// plus whatever the JSON list in `path` adds.
+fn parse_walls(path: Option<&str>, tg_chat_id: &str) -> anyhow::Result<Vec<WallConfig>> {
+    let configured: Vec<WallConfig> = match path {
+        Some(path) => serde_json::from_str(&std::fs::read_to_string(path)?)
+            .map_err(|e| anyhow!("Invalid walls config {}: {}", path, e))?,
+        None => Vec::new(),
+    };
+
+    let mut walls = vec![WallConfig {
+        name: DEFAULT_WALL.to_string(),
+        tg_chat_id: Some(tg_chat_id.to_string()),
+        rate_limit: default_rate_limit(),
+        rate_window_secs: default_rate_window_secs(),
+        max_ttl_secs: default_max_ttl_secs(),
+        webhooks: Vec::new(),
+        discord_webhook: None,
+        matrix_room_id: None,
+    }];
+    for wall in configured {
+        // it ends up in urls, so nothing that would need escaping
+        let valid = !wall.name.is_empty() && wall.name.len() <= 32
+            && wall.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            bail!("Invalid wall name: {:?}", wall.name);
+        }
+        if wall.rate_limit == 0 || wall.rate_window_secs == 0 {
+            bail!("Wall {} needs a non-zero rate limit and window", wall.name);
+        }
+        for (i, webhook) in wall.webhooks.iter().enumerate() {
+            if !webhook.url.starts_with("http://") && !webhook.url.starts_with("https://") {
+                bail!("Webhook {:?} of wall {} has to be an http(s) URL", webhook.url, wall.name);
+            }
+            if webhook.secret.is_empty() {
+                bail!("Webhook {} of wall {} needs a secret", webhook.url, wall.name);
+            }
+            // it names the webhook in the outbox
+            if wall.webhooks[..i].iter().any(|other| other.url == webhook.url) {
+                bail!("Webhook {} of wall {} is configured twice", webhook.url, wall.name);
+            }
+        }
+        if let Some(url) = &wall.discord_webhook
+            && !url.starts_with("https://") {
+            bail!("Discord webhook of wall {} has to be an https URL", wall.name);
+        }
+        match walls.iter().position(|known| known.name == wall.name) {
+            Some(0) => walls[0] = wall,
+            Some(_) => bail!("Wall {} is configured twice", wall.name),
+            None => walls.push(wall),
+        }
+    }
+    Ok(walls)
+}
+
+fn parse_dispatcher() -> anyhow::Result<DispatcherConfig> {
+    let config = DispatcherConfig {
+        workers: std::env::var("INTEGRATION_WORKERS")
+            .unwrap_or("4".to_string())
+            .parse()?,
+        queue_capacity: std::env::var("INTEGRATION_QUEUE")
+            .unwrap_or("1000".to_string())
+            .parse()?,
+        max_attempts: std::env::var("INTEGRATION_ATTEMPTS")
+            .unwrap_or("5".to_string())
+            .parse()?,
+    };
+    if config.workers == 0 || config.queue_capacity == 0 || config.max_attempts == 0 {
+        bail!("INTEGRATION_WORKERS, INTEGRATION_QUEUE and INTEGRATION_ATTEMPTS have to be above 0");
+    }
+    Ok(config)
 }
 
 pub fn parse_args() -> anyhow::Result<Args> {
+    let mut cli = std::env::args().skip(1);
+    let command = match cli.next().as_deref() {
+        None | Some("serve") => Command::Serve,
+        Some("migrate") => Command::Migrate,
+        Some("export") => {
+            let (path, wall, format) = parse_dump_args(cli, true)?;
+            Command::Export { path, wall, format }
+        },
+        Some("import") => {
+            let (path, _, format) = parse_dump_args(cli, false)?;
+            Command::Import { path, format }
+        },
+        Some(other) => return Err(anyhow::anyhow!("Unknown command: {}", other)),
+    };
+
+    // only serving talks to Telegram, the other commands go without its credentials
+    let serving = matches!(command, Command::Serve);
+    let telegram_var = |name| match std::env::var(name) {
+        Err(_) if !serving => Ok(String::new()),
+        var => var,
+    };
+
+    let tg_chat_id = telegram_var("TG_CHAT_ID")?;
+    let walls = parse_walls(std::env::var("WALLS_CONFIG").ok().as_deref(), &tg_chat_id)?;
+    let matrix = parse_matrix()?;
+    if let Some(wall) = walls.iter().find(|wall| wall.matrix_room_id.is_some())
+        && matrix.is_none() {
+        bail!("Wall {} has a Matrix room, but MATRIX_HOMESERVER and MATRIX_ACCESS_TOKEN aren't set", wall.name);
+    }
+
     Ok(Args {
+        command,
         port: std::env::var("PORT")
             .unwrap_or("8080".to_string())
             .parse()?,
-        #[cfg(feature = "sqlite_db")]
-        filename: std::env::var("DB_FILENAME")
-            .unwrap_or("db.sqlite".to_string()),
+        database_url: std::env::var("DATABASE_URL")
+            .or_else(|_| std::env::var("DB_FILENAME").map(|filename| format!("sqlite://{}", filename)))
+            .unwrap_or(DEFAULT_DATABASE_URL.to_string()),
+        db_pool: PoolConfig {
+            max_connections: std::env::var("DB_MAX_CONNECTIONS")
+                .unwrap_or("10".to_string())
+                .parse()?,
+            min_connections: std::env::var("DB_MIN_CONNECTIONS")
+                .unwrap_or("1".to_string())
+                .parse()?,
+            acquire_timeout_secs: std::env::var("DB_ACQUIRE_TIMEOUT")
+                .unwrap_or("30".to_string())
+                .parse()?,
+        },
         repo_url: std::env::var("REPO_URL")
             .unwrap_or("https://github.com/miko089/wall".to_string()),
-        tg_token: std::env::var("TG_TOKEN")?,
-        tg_chat_id: std::env::var("TG_CHAT_ID")?,
+        tg_token: telegram_var("TG_TOKEN")?,
+        // no token, no admin API
+        admin_token: std::env::var("ADMIN_TOKEN").ok().filter(|token| !token.is_empty()),
+        admin_audit_log: std::env::var("ADMIN_AUDIT_LOG")
+            .unwrap_or("audit.jsonl".to_string()),
+        walls,
+        retention: parse_retention()?,
+        sweep_interval_secs: match std::env::var("SWEEP_INTERVAL").unwrap_or("10".to_string()).parse()? {
+            0 => bail!("SWEEP_INTERVAL has to be above 0"),
+            secs => secs,
+        },
+        dispatcher: parse_dispatcher()?,
+        matrix,
     })
 }
 
@@ -0,0 +1,208 @@
+//! Whole walls as files, to move them between servers and backends:
+//! JSON Lines with one message per line, or CSV with one per row.
+
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::sync::Arc;
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use crate::database::{Database, ExportedMsg, Msg, MsgMeta};
+
+// messages fetched per query while exporting
+const EXPORT_BATCH: u32 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Jsonl,
+    Csv,
+}
+
+impl Format {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "jsonl" => Ok(Self::Jsonl),
+            "csv" => Ok(Self::Csv),
+            other => Err(anyhow!("Unknown dump format: {}", other)),
+        }
+    }
+
+    /// Going by the extension, JSON Lines unless it is `.csv`.
+    pub fn of_path(path: &str) -> Self {
+        if path.ends_with(".csv") { Self::Csv } else { Self::Jsonl }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Jsonl => "application/jsonl",
+            Self::Csv => "text/csv",
+        }
+    }
+}
+
+/// A message flattened into CSV columns.
+#[derive(Serialize, Deserialize)]
+struct CsvRow {
+    id: u32,
+    wall: Arc<str>,
+    author: Arc<str>,
+    content: Arc<str>,
+    timestamp: u64,
+    edited_at: Option<u64>,
+    reply_to: Option<u32>,
+    pinned: bool,
+    pinned_until: Option<u64>,
+    deleted_at: Option<u64>,
+    // dumps from before ephemeral messages lack the column
+    #[serde(default)]
+    expires_at: Option<u64>,
+    delete_token_hash: Option<Arc<str>>,
+    client_ip: Option<Arc<str>>,
+    /// JSON, `{"👍": ["1.2.3.4"]}`, CSV has nothing nested
+    reacted: String,
+}
+
+impl CsvRow {
+    fn new(ExportedMsg { msg, meta, reacted }: ExportedMsg) -> Result<Self> {
+        Ok(Self {
+            id: msg.id,
+            wall: msg.wall,
+            author: msg.author,
+            content: msg.content,
+            timestamp: msg.timestamp,
+            edited_at: msg.edited_at,
+            reply_to: msg.reply_to,
+            pinned: msg.pinned,
+            pinned_until: msg.pinned_until,
+            deleted_at: msg.deleted_at,
+            expires_at: msg.expires_at,
+            delete_token_hash: meta.delete_token_hash,
+            client_ip: meta.client_ip,
+            reacted: serde_json::to_string(&reacted)?,
+        })
+    }
+
+    fn into_msg(self) -> Result<ExportedMsg> {
+        let reacted: BTreeMap<Arc<str>, Vec<Arc<str>>> = match self.reacted.as_str() {
+            "" => BTreeMap::new(),
+            json => serde_json::from_str(json)?,
+        };
+        Ok(ExportedMsg {
+            msg: Msg {
+                id: self.id,
+                wall: self.wall,
+                author: self.author,
+                content: self.content,
+                timestamp: self.timestamp,
+                edited_at: self.edited_at,
+                reply_to: self.reply_to,
+                pinned: self.pinned,
+                pinned_until: self.pinned_until,
+                reactions: reacted.iter().map(|(emoji, ips)| (emoji.clone(), ips.len() as u32)).collect(),
+                deleted_at: self.deleted_at,
+                expires_at: self.expires_at,
+            },
+            meta: MsgMeta {
+                delete_token_hash: self.delete_token_hash,
+                client_ip: self.client_ip,
+            },
+            reacted,
+        })
+    }
+}
+
+enum Writer<W: Write> {
+    Jsonl(W),
+    // boxed, it carries a sizeable buffer
+    Csv(Box<csv::Writer<W>>),
+}
+
+impl<W: Write> Writer<W> {
+    fn write(&mut self, exported: ExportedMsg) -> Result<()> {
+        match self {
+            Self::Jsonl(out) => {
+                let mut line = serde_json::to_string(&exported)?;
+                line.push('\n');
+                out.write_all(line.as_bytes())?;
+            },
+            Self::Csv(csv) => csv.serialize(CsvRow::new(exported)?)?,
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Self::Jsonl(out) => out.flush()?,
+            Self::Csv(csv) => csv.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// Writes every message of `wall`, or of all walls, to `out` oldest first. Returns how many.
+pub async fn export(db: &dyn Database, wall: Option<&str>, format: Format, out: impl Write) -> Result<usize> {
+    let mut writer = match format {
+        Format::Jsonl => Writer::Jsonl(out),
+        Format::Csv => Writer::Csv(Box::new(csv::Writer::from_writer(out))),
+    };
+    let mut after = 0;
+    let mut count = 0;
+    loop {
+        let batch = db.export(wall, after, EXPORT_BATCH).await?;
+        let Some(last) = batch.last() else {
+            break;
+        };
+        after = last.msg.id;
+        count += batch.len();
+        for exported in batch {
+            writer.write(exported)?;
+        }
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Appends messages to the JSON Lines dump at `path`, creating it if needed.
+/// They are on disk once this returns.
+pub fn append(path: &str, msgs: Vec<ExportedMsg>) -> Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut writer = Writer::Jsonl(BufWriter::new(&file));
+    for exported in msgs {
+        writer.write(exported)?;
+    }
+    writer.flush()?;
+    file.sync_data()?;
+    Ok(())
+}
+
+/// Reads back what `export` wrote.
+pub fn read(format: Format, input: impl Read) -> Result<Vec<ExportedMsg>> {
+    let msgs: Vec<ExportedMsg> = match format {
+        Format::Jsonl => {
+            let mut msgs = Vec::new();
+            for (number, line) in BufReader::new(input).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let exported = serde_json::from_str(&line)
+                    .map_err(|e| anyhow!("Invalid dump line {}: {}", number + 1, e))?;
+                msgs.push(exported);
+            }
+            msgs
+        },
+        Format::Csv => csv::Reader::from_reader(input)
+            .deserialize()
+            .enumerate()
+            .map(|(number, row)| {
+                // the header is line 1
+                let row: CsvRow = row.map_err(|e| anyhow!("Invalid dump row {}: {}", number + 2, e))?;
+                row.into_msg()
+            })
+            .collect::<Result<_>>()?,
+    };
+    if msgs.iter().any(|exported| exported.msg.id == 0) {
+        bail!("Message ids start at 1");
+    }
+    Ok(msgs)
+}
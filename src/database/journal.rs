@@ -0,0 +1,466 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use crate::database::{Ban, Database, Delivery, ExportedMsg, GetMsgs, Msg, MsgFilter, MsgMeta, RawMsg, ReceiveMsg};
+use crate::database::mock::{MockBase, Wall};
+use crate::database::search::SearchHit;
+
+/// When appended entries are forced to disk.
+#[derive(Debug, Clone, Copy)]
+pub enum FsyncPolicy {
+    /// after every message, nothing acknowledged is ever lost
+    Always,
+    /// after every n-th message, a crash loses at most the last n - 1
+    Every(u32),
+    /// whenever the OS decides to
+    Never,
+}
+
+impl FsyncPolicy {
+    fn parse(s: &str) -> Result<Self> {
+        match s {
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            n => match n.parse() {
+                Ok(0) | Err(_) => Err(anyhow!("Invalid fsync policy: {}", n)),
+                Ok(n) => Ok(Self::Every(n)),
+            }
+        }
+    }
+}
+
+/// One line of the journal.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Entry {
+    Msg {
+        #[serde(flatten)]
+        msg: Msg,
+        #[serde(flatten)]
+        meta: MsgMeta,
+        /// integrations it still has to be posted to
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        deliver_to: Vec<Arc<str>>,
+    },
+    Delivered { id: u32, integration: Arc<str> },
+    /// a whole import on one line, so a crash can't leave part of it behind
+    Import { msgs: Vec<ExportedMsg> },
+    Delete { id: u32, at: u64 },
+    Edit { id: u32, content: Arc<str>, at: u64 },
+    Pin {
+        id: u32,
+        pinned: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        until: Option<u64>,
+    },
+    Ban { ip: Arc<str>, at: u64 },
+    Unban { ip: Arc<str> },
+    React { id: u32, emoji: Arc<str>, ip: Arc<str> },
+    Unreact { id: u32, emoji: Arc<str>, ip: Arc<str> },
+    Prune { wall: Arc<str>, up_to: u32 },
+    /// ephemeral messages removed once they ran out
+    Expire { ids: Vec<u32> },
+    /// written by compaction when the newest ids were pruned, so they aren't handed out again
+    LastId { id: u32 },
+}
+
+struct Writer {
+    file: Arc<File>,
+    /// of everything appended successfully, a failed append is cut back to it
+    len: u64,
+    policy: FsyncPolicy,
+    unsynced: u32,
+}
+
+impl Writer {
+    /// Writes the entry down before anything in memory changes, on disk as far as
+    /// the policy asks once this returns. Nothing of it stays if it fails.
+    async fn append(&mut self, entry: &Entry) -> Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        let sync = match self.policy {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::Every(n) => self.unsynced + 1 >= n,
+            FsyncPolicy::Never => false,
+        };
+
+        let (file, len) = (self.file.clone(), self.len);
+        let written = line.len() as u64;
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let appended = (&*file).write_all(line.as_bytes())
+                .and_then(|()| if sync { file.sync_data() } else { Ok(()) });
+            if let Err(e) = appended {
+                // a half line would make every entry after it unreadable
+                file.set_len(len)?;
+                return Err(e.into());
+            }
+            Ok(())
+        }).await??;
+
+        self.len += written;
+        self.unsynced = if sync { 0 } else { self.unsynced + 1 };
+        Ok(())
+    }
+}
+
+/// `MockBase` that survives restarts: every change is appended to a JSON Lines file
+/// and replayed from it on startup.
+#[derive(Clone)]
+pub struct Journal {
+    base: MockBase,
+    writer: Arc<Mutex<Writer>>,
+}
+
+impl Journal {
+    /// `location` is `<path>[?fsync=always|never|<n>]`, fsync defaults to `always`.
+    pub fn open(location: &str) -> Result<Self> {
+        let (path, query) = location.split_once('?').unwrap_or((location, ""));
+        let mut policy = FsyncPolicy::Always;
+        for option in query.split('&').filter(|option| !option.is_empty()) {
+            match option.split_once('=') {
+                Some(("fsync", value)) => policy = FsyncPolicy::parse(value)?,
+                _ => bail!("Unknown journal option: {}", option),
+            }
+        }
+
+        let path = std::env::current_dir()?.join(path);
+        let (wall, stale) = replay(&path)?;
+        if stale > 0 {
+            tracing::info!("Compacting journal {}, dropping {} stale entries", path.display(), stale);
+            compact(&path, &wall)?;
+        }
+        tracing::info!("Replayed {} messages from {}", wall.msgs.len(), path.display());
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let len = file.metadata()?.len();
+        Ok(Self {
+            base: MockBase::from_wall(wall),
+            writer: Arc::new(Mutex::new(Writer { file: Arc::new(file), len, policy, unsynced: 0 })),
+        })
+    }
+}
+
+/// Reads the journal back into a wall, also counting entries that don't make
+/// it into that state as they are (superseded ones or a torn last line).
+fn replay(path: &Path) -> Result<(Wall, usize)> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Wall::default(), 0)),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut msgs = BTreeMap::new();
+    let mut wall = Wall::default();
+    let mut entries = 0;
+    let mut lines = BufReader::new(file).lines().enumerate().peekable();
+    while let Some((number, line)) = lines.next() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(Entry::Msg { msg, meta, deliver_to }) => {
+                for integration in deliver_to {
+                    wall.pending.insert((msg.id, integration));
+                }
+                wall.last_id = wall.last_id.max(msg.id);
+                wall.meta.insert(msg.id, meta);
+                msgs.insert(msg.id, Arc::new(msg));
+            },
+            Ok(Entry::Import { msgs: imported }) => {
+                // counted as the entries it stands for, so the stale count adds up
+                entries += imported.len().saturating_sub(1);
+                for ExportedMsg { msg, meta, reacted } in imported {
+                    wall.last_id = wall.last_id.max(msg.id);
+                    wall.meta.insert(msg.id, meta);
+                    for (emoji, ips) in reacted.into_iter().filter(|(_, ips)| !ips.is_empty()) {
+                        entries += ips.len();
+                        wall.reactions.entry(msg.id).or_default().entry(emoji).or_default().extend(ips);
+                    }
+                    msgs.insert(msg.id, Arc::new(msg));
+                }
+            },
+            Ok(Entry::Delivered { id, integration }) => {
+                wall.pending.remove(&(id, integration));
+            },
+            Ok(Entry::Delete { id, at }) => {
+                if let Some(msg) = msgs.get_mut(&id) {
+                    Arc::make_mut(msg).deleted_at = Some(at);
+                }
+            },
+            Ok(Entry::Edit { id, content, at }) => {
+                if let Some(msg) = msgs.get_mut(&id) {
+                    let msg = Arc::make_mut(msg);
+                    msg.content = content;
+                    msg.edited_at = Some(at);
+                }
+            },
+            Ok(Entry::Pin { id, pinned, until }) => {
+                if let Some(msg) = msgs.get_mut(&id) {
+                    let msg = Arc::make_mut(msg);
+                    msg.pinned = pinned;
+                    msg.pinned_until = until.filter(|_| pinned);
+                }
+            },
+            Ok(Entry::Ban { ip, at }) => {
+                wall.bans.insert(ip, at);
+            },
+            Ok(Entry::Unban { ip }) => {
+                wall.bans.remove(&ip);
+            },
+            Ok(Entry::React { id, emoji, ip }) => {
+                if msgs.contains_key(&id) {
+                    wall.reactions.entry(id).or_default().entry(emoji).or_default().insert(ip);
+                }
+            },
+            Ok(Entry::Unreact { id, emoji, ip }) => {
+                if let Some(ips) = wall.reactions.get_mut(&id).and_then(|by_emoji| by_emoji.get_mut(&emoji)) {
+                    ips.remove(&ip);
+                }
+            },
+            Ok(Entry::Prune { wall: pruned, up_to }) => {
+                let ids: Vec<_> = msgs.range(..=up_to)
+                    .filter(|(_, msg)| msg.wall == pruned)
+                    .map(|(id, _)| *id)
+                    .collect();
+                forget(&mut msgs, &mut wall, &ids);
+            },
+            Ok(Entry::Expire { ids }) => {
+                forget(&mut msgs, &mut wall, &ids);
+            },
+            Ok(Entry::LastId { id }) => {
+                wall.last_id = wall.last_id.max(id);
+            },
+            // a crash mid-append leaves half a line at the very end, anything else is corruption
+            Err(e) if lines.peek().is_none() => {
+                tracing::warn!("Ignoring torn last line {} of journal: {}", number + 1, e);
+            },
+            Err(e) => bail!("Corrupted journal line {}: {}", number + 1, e),
+        }
+        entries += 1;
+    }
+
+    wall.msgs = msgs.into_values().collect();
+    let reactions: usize = wall.reactions.values().flat_map(|by_emoji| by_emoji.values()).map(|ips| ips.len()).sum();
+    let stale = entries - wall.msgs.len() - wall.bans.len() - reactions - usize::from(pruned_last(&wall));
+    Ok((wall, stale))
+}
+
+/// Replays the removal of messages, replies to them stop being replies.
+fn forget(msgs: &mut BTreeMap<u32, Arc<Msg>>, wall: &mut Wall, ids: &[u32]) {
+    let ids: HashSet<_> = ids.iter().copied().collect();
+    for id in &ids {
+        msgs.remove(id);
+        wall.meta.remove(id);
+        wall.reactions.remove(id);
+    }
+    wall.pending.retain(|(id, _)| !ids.contains(id));
+    for msg in msgs.values_mut() {
+        if msg.reply_to.is_some_and(|parent| ids.contains(&parent)) {
+            Arc::make_mut(msg).reply_to = None;
+        }
+    }
+}
+
+/// Whether the newest id handed out went with pruned messages, and so needs an entry of its own.
+fn pruned_last(wall: &Wall) -> bool {
+    wall.last_id > wall.msgs.last().map_or(0, |msg| msg.id)
+}
+
+/// Atomically replaces the journal with one entry per message, ban and reaction,
+/// pending deliveries going along with their message.
+fn compact(path: &Path, wall: &Wall) -> Result<()> {
+    let mut tmp = PathBuf::from(path);
+    tmp.as_mut_os_string().push(".compact");
+
+    let msgs = wall.msgs.iter().map(|msg| Entry::Msg {
+        msg: msg.as_ref().clone(),
+        meta: wall.meta.get(&msg.id).cloned().unwrap_or_default(),
+        deliver_to: wall.pending.range((msg.id, Arc::from(""))..)
+            .take_while(|(id, _)| *id == msg.id)
+            .map(|(_, integration)| integration.clone())
+            .collect(),
+    });
+    let bans = wall.bans.iter().map(|(ip, at)| Entry::Ban { ip: ip.clone(), at: *at });
+    let reactions = wall.reactions.iter().flat_map(|(id, by_emoji)| {
+        by_emoji.iter().flat_map(move |(emoji, ips)| {
+            ips.iter().map(move |ip| Entry::React { id: *id, emoji: emoji.clone(), ip: ip.clone() })
+        })
+    });
+
+    let last_id = pruned_last(wall).then_some(Entry::LastId { id: wall.last_id });
+
+    let mut file = File::create(&tmp)?;
+    for entry in msgs.chain(bans).chain(reactions).chain(last_id) {
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+    }
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+// Every change is checked against memory, written down and only then made in memory,
+// all under the writer lock, so a failed write leaves nothing behind.
+#[async_trait::async_trait]
+impl Database for Journal {
+    async fn get_msgs(&self, wall: &str, count: GetMsgs, filter: &MsgFilter, limit: u32) -> Result<Vec<Arc<Msg>>> {
+        self.base.get_msgs(wall, count, filter, limit).await
+    }
+
+    async fn send_msg(&self, msg: ReceiveMsg) -> Result<Arc<Msg>> {
+        // held across every step, so the journal sees messages in id order
+        let mut writer = self.writer.lock().await;
+        let at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let next = self.base.next_msg(&msg, at);
+        writer.append(&Entry::Msg { msg: next.clone(), meta: msg.meta.clone(), deliver_to: msg.deliver_to.clone() }).await?;
+        Ok(self.base.insert(next, msg.meta, msg.deliver_to))
+    }
+
+    async fn last_msg(&self, wall: &str) -> Result<u32> {
+        self.base.last_msg(wall).await
+    }
+
+    async fn delete_msg(&self, id: u32) -> Result<bool> {
+        let mut writer = self.writer.lock().await;
+        if self.base.get_msg(id).await?.is_none() {
+            return Ok(false);
+        }
+        let at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        writer.append(&Entry::Delete { id, at }).await?;
+        Ok(self.base.delete_at(id, at))
+    }
+
+    async fn get_msg(&self, id: u32) -> Result<Option<Arc<Msg>>> {
+        self.base.get_msg(id).await
+    }
+
+    async fn delete_token_hash(&self, id: u32) -> Result<Option<Arc<str>>> {
+        self.base.delete_token_hash(id).await
+    }
+
+    async fn edit_msg(&self, id: u32, content: Arc<str>) -> Result<Option<Arc<Msg>>> {
+        let mut writer = self.writer.lock().await;
+        if self.base.get_msg(id).await?.is_none() {
+            return Ok(None);
+        }
+        let at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        writer.append(&Entry::Edit { id, content: content.clone(), at }).await?;
+        Ok(self.base.edit_at(id, content, at))
+    }
+
+    async fn raw_msg(&self, id: u32) -> Result<Option<RawMsg>> {
+        self.base.raw_msg(id).await
+    }
+
+    async fn set_pinned(&self, id: u32, pinned: bool, until: Option<u64>) -> Result<Option<Arc<Msg>>> {
+        let mut writer = self.writer.lock().await;
+        if self.base.get_msg(id).await?.is_none() {
+            return Ok(None);
+        }
+        writer.append(&Entry::Pin { id, pinned, until: until.filter(|_| pinned) }).await?;
+        self.base.set_pinned(id, pinned, until).await
+    }
+
+    async fn pinned(&self, wall: &str) -> Result<Vec<Arc<Msg>>> {
+        self.base.pinned(wall).await
+    }
+
+    async fn set_banned(&self, ip: &str, banned: bool) -> Result<bool> {
+        let mut writer = self.writer.lock().await;
+        if self.base.is_banned(ip).await? == banned {
+            return Ok(false);
+        }
+        let at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let entry_ip = Arc::from(ip);
+        writer.append(&if banned { Entry::Ban { ip: entry_ip, at } } else { Entry::Unban { ip: entry_ip } }).await?;
+        Ok(self.base.ban_at(ip, banned.then_some(at)))
+    }
+
+    async fn is_banned(&self, ip: &str) -> Result<bool> {
+        self.base.is_banned(ip).await
+    }
+
+    async fn banned_ips(&self) -> Result<Vec<Ban>> {
+        self.base.banned_ips().await
+    }
+
+    async fn search(&self, wall: &str, query: &str, count: GetMsgs, limit: u32) -> Result<Vec<SearchHit>> {
+        self.base.search(wall, query, count, limit).await
+    }
+
+    async fn thread(&self, id: u32) -> Result<Vec<Arc<Msg>>> {
+        self.base.thread(id).await
+    }
+
+    async fn react(&self, id: u32, emoji: &str, ip: &str, on: bool) -> Result<Option<Arc<Msg>>> {
+        let mut writer = self.writer.lock().await;
+        if self.base.get_msg(id).await?.is_none() {
+            return Ok(None);
+        }
+        let (entry_emoji, entry_ip) = (Arc::from(emoji), Arc::from(ip));
+        writer.append(&if on {
+            Entry::React { id, emoji: entry_emoji, ip: entry_ip }
+        } else {
+            Entry::Unreact { id, emoji: entry_emoji, ip: entry_ip }
+        }).await?;
+        self.base.react(id, emoji, ip, on).await
+    }
+
+    async fn export(&self, wall: Option<&str>, after: u32, limit: u32) -> Result<Vec<ExportedMsg>> {
+        self.base.export(wall, after, limit).await
+    }
+
+    async fn import(&self, msgs: Vec<ExportedMsg>) -> Result<()> {
+        let mut writer = self.writer.lock().await;
+        self.base.check_import(&msgs)?;
+        writer.append(&Entry::Import { msgs: msgs.clone() }).await?;
+        self.base.import(msgs).await
+    }
+
+    async fn prunable(&self, wall: &str, sent_before: Option<u64>, keep: Option<u32>) -> Result<u32> {
+        self.base.prunable(wall, sent_before, keep).await
+    }
+
+    async fn prune(&self, wall: &str, up_to: u32) -> Result<u64> {
+        let mut writer = self.writer.lock().await;
+        // the oldest one, if it isn't covered nothing is
+        let oldest = self.base.export(Some(wall), 0, 1).await?;
+        if oldest.first().is_none_or(|exported| exported.msg.id > up_to) {
+            return Ok(0);
+        }
+        writer.append(&Entry::Prune { wall: Arc::from(wall), up_to }).await?;
+        self.base.prune(wall, up_to).await
+    }
+
+    async fn remove_expired(&self, now: u64) -> Result<Vec<Arc<Msg>>> {
+        let mut writer = self.writer.lock().await;
+        let ids = self.base.expired(now);
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        writer.append(&Entry::Expire { ids }).await?;
+        self.base.remove_expired(now).await
+    }
+
+    async fn pending_deliveries(&self) -> Result<Vec<Delivery>> {
+        self.base.pending_deliveries().await
+    }
+
+    async fn delivered(&self, id: u32, integration: &str) -> Result<()> {
+        let mut writer = self.writer.lock().await;
+        if !self.base.is_pending(id, integration) {
+            return Ok(());
+        }
+        writer.append(&Entry::Delivered { id, integration: Arc::from(integration) }).await?;
+        self.base.deliver(id, integration);
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+use anyhow::{bail, Result};
+use sea_orm::{ConnectionTrait, DatabaseConnection, DbBackend, Statement, TransactionTrait};
+
+// n-th entry brings the schema from version n to n + 1, never edit applied ones.
+// Both lists must stay the same length, a version means the same schema on every backend.
+const SQLITE: &[&[&str]] = &[
+    &["CREATE TABLE IF NOT EXISTS messages (id INTEGER PRIMARY KEY AUTOINCREMENT,
+        author TEXT NOT NULL,
+        content TEXT NOT NULL,
+        timestamp INTEGER NOT NULL);"],
+    &["ALTER TABLE messages ADD COLUMN deleted_at INTEGER;"],
+    &["ALTER TABLE messages ADD COLUMN edited_at INTEGER;",
+      "ALTER TABLE messages ADD COLUMN delete_token_hash TEXT;"],
+    &["ALTER TABLE messages ADD COLUMN client_ip TEXT;",
+      "ALTER TABLE messages ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;",
+      "CREATE TABLE bans (ip TEXT PRIMARY KEY, banned_at INTEGER NOT NULL);"],
+    &["CREATE VIRTUAL TABLE messages_fts USING fts5(content, content='messages', content_rowid='id');",
+      "INSERT INTO messages_fts(messages_fts) VALUES ('rebuild');",
+      "CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages BEGIN
+          INSERT INTO messages_fts(rowid, content) VALUES (new.id, new.content);
+      END;",
+      "CREATE TRIGGER messages_fts_update AFTER UPDATE OF content ON messages BEGIN
+          INSERT INTO messages_fts(messages_fts, rowid, content) VALUES ('delete', old.id, old.content);
+          INSERT INTO messages_fts(rowid, content) VALUES (new.id, new.content);
+      END;",
+      "CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN
+          INSERT INTO messages_fts(messages_fts, rowid, content) VALUES ('delete', old.id, old.content);
+      END;"],
+    &["ALTER TABLE messages ADD COLUMN reply_to INTEGER REFERENCES messages(id);",
+      "CREATE INDEX messages_reply_to ON messages(reply_to);"],
+    &["CREATE TABLE reactions (msg_id INTEGER NOT NULL REFERENCES messages(id),
+        emoji TEXT NOT NULL,
+        ip TEXT NOT NULL,
+        PRIMARY KEY (msg_id, emoji, ip));"],
+    &["ALTER TABLE messages ADD COLUMN wall TEXT NOT NULL DEFAULT 'main';",
+      "CREATE INDEX messages_wall ON messages(wall, id);"],
+    &["ALTER TABLE messages ADD COLUMN pinned_until INTEGER;",
+      "CREATE INDEX messages_pinned ON messages(wall) WHERE pinned;"],
+    &["ALTER TABLE messages ADD COLUMN expires_at INTEGER;",
+      "CREATE INDEX messages_expires_at ON messages(expires_at) WHERE expires_at IS NOT NULL;"],
+    &["CREATE TABLE deliveries (msg_id INTEGER NOT NULL REFERENCES messages(id),
+        integration TEXT NOT NULL,
+        delivered_at INTEGER,
+        PRIMARY KEY (msg_id, integration));",
+      "CREATE INDEX deliveries_pending ON deliveries(msg_id) WHERE delivered_at IS NULL;"],
+];
+
+const POSTGRES: &[&[&str]] = &[
+    &["CREATE TABLE IF NOT EXISTS messages (id SERIAL PRIMARY KEY,
+        author TEXT NOT NULL,
+        content TEXT NOT NULL,
+        timestamp BIGINT NOT NULL);"],
+    &["ALTER TABLE messages ADD COLUMN deleted_at BIGINT;"],
+    &["ALTER TABLE messages ADD COLUMN edited_at BIGINT;",
+      "ALTER TABLE messages ADD COLUMN delete_token_hash TEXT;"],
+    &["ALTER TABLE messages ADD COLUMN client_ip TEXT;",
+      "ALTER TABLE messages ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT FALSE;",
+      "CREATE TABLE bans (ip TEXT PRIMARY KEY, banned_at BIGINT NOT NULL);"],
+    &["CREATE INDEX messages_search ON messages USING GIN (to_tsvector('simple', content));"],
+    &["ALTER TABLE messages ADD COLUMN reply_to INTEGER REFERENCES messages(id);",
+      "CREATE INDEX messages_reply_to ON messages(reply_to);"],
+    &["CREATE TABLE reactions (msg_id INTEGER NOT NULL REFERENCES messages(id),
+        emoji TEXT NOT NULL,
+        ip TEXT NOT NULL,
+        PRIMARY KEY (msg_id, emoji, ip));"],
+    &["ALTER TABLE messages ADD COLUMN wall TEXT NOT NULL DEFAULT 'main';",
+      "CREATE INDEX messages_wall ON messages(wall, id);"],
+    &["ALTER TABLE messages ADD COLUMN pinned_until BIGINT;",
+      "CREATE INDEX messages_pinned ON messages(wall) WHERE pinned;"],
+    &["ALTER TABLE messages ADD COLUMN expires_at BIGINT;",
+      "CREATE INDEX messages_expires_at ON messages(expires_at) WHERE expires_at IS NOT NULL;"],
+    &["CREATE TABLE deliveries (msg_id INTEGER NOT NULL REFERENCES messages(id),
+        integration TEXT NOT NULL,
+        delivered_at BIGINT,
+        PRIMARY KEY (msg_id, integration));",
+      "CREATE INDEX deliveries_pending ON deliveries(msg_id) WHERE delivered_at IS NULL;"],
+];
+
+pub const LATEST: u32 = SQLITE.len() as u32;
+
+fn migrations(backend: DbBackend) -> Result<&'static [&'static [&'static str]]> {
+    match backend {
+        DbBackend::Sqlite => Ok(SQLITE),
+        DbBackend::Postgres => Ok(POSTGRES),
+        other => bail!("No migrations for {:?}", other),
+    }
+}
+
+pub async fn version(db: &DatabaseConnection) -> Result<u32> {
+    let backend = db.get_database_backend();
+    let query = match backend {
+        // sqlite keeps it in the file header, postgres gets a one-row table
+        DbBackend::Sqlite => "PRAGMA user_version;",
+        _ => {
+            db.execute_unprepared("CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL);").await?;
+            "SELECT version FROM schema_version;"
+        }
+    };
+    let row = db.query_one(Statement::from_string(backend, query)).await?;
+    Ok(match row {
+        Some(row) => row.try_get_by_index::<i32>(0)? as u32,
+        None => 0,
+    })
+}
+
+/// Applies every migration newer than the recorded schema version, each in its own transaction.
+pub async fn run(db: &DatabaseConnection) -> Result<()> {
+    let backend = db.get_database_backend();
+    let migrations = migrations(backend)?;
+    let current = version(db).await?;
+    if current > LATEST {
+        bail!("Database schema version {} is newer than this binary supports ({})", current, LATEST);
+    }
+
+    for (version, statements) in (1..).zip(migrations).skip(current as usize) {
+        let txn = db.begin().await?;
+        for statement in statements.iter() {
+            txn.execute_unprepared(statement).await?;
+        }
+        match backend {
+            DbBackend::Sqlite => {
+                txn.execute_unprepared(&format!("PRAGMA user_version = {};", version)).await?;
+            },
+            _ => {
+                txn.execute_unprepared("DELETE FROM schema_version;").await?;
+                txn.execute_unprepared(&format!("INSERT INTO schema_version VALUES ({});", version)).await?;
+            }
+        }
+        txn.commit().await?;
+        tracing::info!("Applied migration {}", version);
+    }
+    Ok(())
+}
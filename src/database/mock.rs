@@ -1,70 +1,485 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::sync::{RwLock, Arc};
 use std::time;
 use std::time::UNIX_EPOCH;
 use anyhow::Result;
-use crate::database::{Database, GetMsgs, Msg, ReceiveMsg};
-use crate::database::GetMsgs::{Before, After};
+use crate::database::{Ban, Database, Delivery, ExportedMsg, GetMsgs, IdTaken, Msg, MsgFilter, MsgMeta, RawMsg, ReceiveMsg};
+use crate::database::GetMsgs::{Before, After, Latest};
+use crate::database::search::{self, SearchHit, SearchIndex};
 use time::SystemTime;
 
+/// Everything `MockBase` stores.
+#[derive(Default)]
+pub struct Wall {
+    /// ordered by id, tombstones included
+    pub msgs: Vec<Arc<Msg>>,
+    // kept apart, so it never ends up in a `Msg` sent to clients
+    pub meta: HashMap<u32, MsgMeta>,
+    pub bans: HashMap<Arc<str>, u64>,
+    /// who reacted with what, by message id and emoji, counts on `msgs` follow from it
+    pub reactions: HashMap<u32, HashMap<Arc<str>, HashSet<Arc<str>>>>,
+    /// highest id handed out so far, pruned messages may have taken it with them
+    pub last_id: u32,
+    /// deliveries not done yet, by message id and integration name
+    pub pending: BTreeSet<(u32, Arc<str>)>,
+    // of live messages only, rebuilt from `msgs` by `MockBase::from_wall`
+    index: SearchIndex,
+}
+
+impl Wall {
+    fn find(&self, id: u32) -> Option<&Arc<Msg>> {
+        self.msgs.binary_search_by_key(&id, |msg| msg.id)
+            .ok()
+            .map(|index| &self.msgs[index])
+    }
+
+    /// Neither deleted nor expired at unix time `now`.
+    fn find_live(&self, id: u32, now: u64) -> Option<&Arc<Msg>> {
+        self.find(id).filter(|msg| msg.deleted_at.is_none() && !msg.is_expired(now))
+    }
+
+    /// Drops the messages with these ids for good, replies to them stop being replies.
+    fn remove(&mut self, ids: &HashSet<u32>) -> Vec<Arc<Msg>> {
+        let (gone, kept) = std::mem::take(&mut self.msgs)
+            .into_iter()
+            .partition(|msg| ids.contains(&msg.id));
+        self.msgs = kept;
+        for msg in &gone {
+            if msg.deleted_at.is_none() {
+                self.index.remove(msg);
+            }
+            self.meta.remove(&msg.id);
+            self.reactions.remove(&msg.id);
+        }
+        self.pending.retain(|(id, _)| !ids.contains(id));
+        for msg in self.msgs.iter_mut() {
+            if msg.reply_to.is_some_and(|parent| ids.contains(&parent)) {
+                Arc::make_mut(msg).reply_to = None;
+            }
+        }
+        gone
+    }
+
+    /// The message `msg` becomes when sent `at`, with the next id.
+    fn next_msg(&self, msg: &ReceiveMsg, at: u64) -> Msg {
+        Msg {
+            id: self.last_id + 1,
+            wall: msg.wall.clone(),
+            author: msg.author.clone(),
+            content: msg.content.clone(),
+            timestamp: at,
+            edited_at: None,
+            reply_to: msg.reply_to,
+            pinned: false,
+            pinned_until: None,
+            reactions: BTreeMap::new(),
+            deleted_at: None,
+            expires_at: msg.ttl.map(|ttl| at + ttl),
+        }
+    }
+
+    fn insert(&mut self, msg: Msg, meta: MsgMeta, deliver_to: Vec<Arc<str>>) -> Arc<Msg> {
+        let msg = Arc::new(msg);
+        self.last_id = self.last_id.max(msg.id);
+        self.meta.insert(msg.id, meta);
+        for integration in deliver_to {
+            self.pending.insert((msg.id, integration));
+        }
+        self.index.insert(&msg);
+        self.msgs.push(msg.clone());
+        msg
+    }
+
+    fn check_import(&self, msgs: &[ExportedMsg]) -> Result<()> {
+        let mut ids = HashSet::new();
+        for exported in msgs {
+            if self.find(exported.msg.id).is_some() || !ids.insert(exported.msg.id) {
+                return Err(IdTaken(exported.msg.id).into());
+            }
+        }
+        Ok(())
+    }
+
+    fn find_live_mut(&mut self, id: u32, now: u64) -> Option<&mut Msg> {
+        let index = self.msgs.binary_search_by_key(&id, |msg| msg.id).ok()?;
+        if self.msgs[index].deleted_at.is_some() || self.msgs[index].is_expired(now) {
+            return None;
+        }
+        Some(Arc::make_mut(&mut self.msgs[index]))
+    }
+}
+
 #[derive(Clone)]
 pub struct MockBase {
-    base: Arc<RwLock<Vec<Arc<Msg>>>>,
+    base: Arc<RwLock<Wall>>,
 }
 
 impl MockBase {
-    pub fn new() -> Self { 
-        Self { base: Arc::new(RwLock::new(Vec::new())) } 
+    pub fn new() -> Self {
+        Self::from_wall(Wall::default())
+    }
+
+    pub fn from_wall(mut wall: Wall) -> Self {
+        let mut index = SearchIndex::default();
+        for msg in wall.msgs.iter_mut() {
+            let reactions = wall.reactions.get(&msg.id);
+            Arc::make_mut(msg).reactions = reactions.into_iter()
+                .flatten()
+                .filter(|(_, ips)| !ips.is_empty())
+                .map(|(emoji, ips)| (emoji.clone(), ips.len() as u32))
+                .collect();
+            if msg.deleted_at.is_none() {
+                index.insert(msg);
+            }
+        }
+        wall.index = index;
+        wall.last_id = wall.last_id.max(wall.msgs.last().map_or(0, |msg| msg.id));
+        Self { base: Arc::new(RwLock::new(wall)) }
+    }
+
+    /// What `send_msg` would store if sent `at`, for `insert` to store later.
+    pub fn next_msg(&self, msg: &ReceiveMsg, at: u64) -> Msg {
+        self.base.read().unwrap().next_msg(msg, at)
+    }
+
+    pub fn insert(&self, msg: Msg, meta: MsgMeta, deliver_to: Vec<Arc<str>>) -> Arc<Msg> {
+        self.base.write().unwrap().insert(msg, meta, deliver_to)
+    }
+
+    /// Whether `import` would take these, without taking them.
+    pub fn check_import(&self, msgs: &[ExportedMsg]) -> Result<()> {
+        self.base.read().unwrap().check_import(msgs)
+    }
+
+    /// Ids of the messages `remove_expired` would remove.
+    pub fn expired(&self, now: u64) -> Vec<u32> {
+        let guard = self.base.read().unwrap();
+        guard.msgs.iter()
+            .filter(|msg| msg.is_expired(now))
+            .map(|msg| msg.id)
+            .collect()
+    }
+
+    pub fn is_pending(&self, id: u32, integration: &str) -> bool {
+        self.base.read().unwrap().pending.contains(&(id, Arc::from(integration)))
+    }
+
+    /// Replaces the content of a live message, marking it edited `at`.
+    pub fn edit_at(&self, id: u32, content: Arc<str>, at: u64) -> Option<Arc<Msg>> {
+        let mut guard = self.base.write().unwrap();
+        let old = guard.find_live(id, at)?.clone();
+        guard.index.remove(&old);
+        let msg = guard.find_live_mut(id, at)?;
+        msg.content = content;
+        msg.edited_at = Some(at);
+        let msg = guard.find(id).cloned()?;
+        guard.index.insert(&msg);
+        Some(msg)
+    }
+
+    /// Turns a live message into a tombstone dated `at`.
+    pub fn delete_at(&self, id: u32, at: u64) -> bool {
+        let mut guard = self.base.write().unwrap();
+        let Some(msg) = guard.find_live_mut(id, at) else {
+            return false;
+        };
+        msg.deleted_at = Some(at);
+        let msg = msg.clone();
+        guard.index.remove(&msg);
+        true
+    }
+
+    /// Bans an ip since `at`, or lifts the ban when `at` is `None`.
+    pub fn ban_at(&self, ip: &str, at: Option<u64>) -> bool {
+        let mut guard = self.base.write().unwrap();
+        match at {
+            Some(at) if !guard.bans.contains_key(ip) => {
+                guard.bans.insert(Arc::from(ip), at);
+                true
+            },
+            Some(_) => false,
+            None => guard.bans.remove(ip).is_some(),
+        }
+    }
+
+    /// Marks a delivery done, false if it wasn't pending.
+    pub fn deliver(&self, id: u32, integration: &str) -> bool {
+        self.base.write().unwrap().pending.remove(&(id, Arc::from(integration)))
     }
 }
 
 #[async_trait::async_trait]
 impl Database for MockBase{
-    async fn get_msgs(&self, count: GetMsgs, limit: u32) -> Result<Vec<Arc<Msg>>> {
+    async fn get_msgs(&self, wall: &str, count: GetMsgs, filter: &MsgFilter, limit: u32) -> Result<Vec<Arc<Msg>>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
         let guard = self.base.read().unwrap();
-        match count {
+        let msgs = &guard.msgs;
+        let shown = |msg: &&Arc<Msg>| *msg.wall == *wall && msg.deleted_at.is_none() && !msg.is_expired(now) && filter.matches(msg);
+        // msgs are ordered by id, so the cursors just cut them in two
+        Ok(match count {
+            Latest => msgs.iter().rev().filter(shown).take(limit as usize).cloned().collect(),
+            Before(before) => msgs[..msgs.partition_point(|msg| (msg.id as usize) < before)]
+                .iter().rev().filter(shown).take(limit as usize).cloned().collect(),
             After(after) => {
-                if after == 0 {
-                    Ok(guard.iter()
-                        .rev()
-                        .take(limit as usize)
-                        .map(|x| x.clone())
-                        .collect()
-                    )
-                } else {
-                    Ok(guard.iter()
-                        .skip(after)
-                        .take(limit as usize)
-                        .map(|x| x.clone())
-                        .collect()
-                    )
-                }
+                let mut page: Vec<_> = msgs[msgs.partition_point(|msg| msg.id as usize <= after)..]
+                    .iter().filter(shown).take(limit as usize).cloned().collect();
+                page.reverse();
+                page
             },
-            Before(before) => {
-                Ok(guard.iter()
-                    .rev()
-                    .skip(guard.len().saturating_sub(before) + 1)
-                    .take(limit as usize)
-                    .map(|x| x.clone())
-                    .collect()
-                )
+        })
+    }
+
+    async fn send_msg(&self, msg: ReceiveMsg) -> Result<Arc<Msg>> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let mut guard = self.base.write().unwrap();
+        let next = guard.next_msg(&msg, timestamp);
+        Ok(guard.insert(next, msg.meta, msg.deliver_to))
+    }
+
+    async fn last_msg(&self, wall: &str) -> Result<u32> {
+        let guard = self.base.read().unwrap();
+        Ok(guard.msgs.iter().rev().find(|msg| *msg.wall == *wall).map_or(0, |msg| msg.id))
+    }
+
+    async fn delete_msg(&self, id: u32) -> Result<bool> {
+        Ok(self.delete_at(id, SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs()))
+    }
+
+    async fn get_msg(&self, id: u32) -> Result<Option<Arc<Msg>>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let guard = self.base.read().unwrap();
+        Ok(guard.find_live(id, now).cloned())
+    }
+
+    async fn delete_token_hash(&self, id: u32) -> Result<Option<Arc<str>>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let guard = self.base.read().unwrap();
+        if guard.find_live(id, now).is_none() {
+            return Ok(None);
+        }
+        Ok(guard.meta.get(&id).and_then(|meta| meta.delete_token_hash.clone()))
+    }
+
+    async fn edit_msg(&self, id: u32, content: Arc<str>) -> Result<Option<Arc<Msg>>> {
+        Ok(self.edit_at(id, content, SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs()))
+    }
+
+    async fn raw_msg(&self, id: u32) -> Result<Option<RawMsg>> {
+        let guard = self.base.read().unwrap();
+        Ok(guard.find(id).map(|msg| RawMsg {
+            msg: msg.clone(),
+            client_ip: guard.meta.get(&id).and_then(|meta| meta.client_ip.clone()),
+        }))
+    }
+
+    async fn set_pinned(&self, id: u32, pinned: bool, until: Option<u64>) -> Result<Option<Arc<Msg>>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let mut guard = self.base.write().unwrap();
+        let Some(msg) = guard.find_live_mut(id, now) else {
+            return Ok(None);
+        };
+        msg.pinned = pinned;
+        msg.pinned_until = until.filter(|_| pinned);
+        Ok(guard.find(id).cloned())
+    }
+
+    async fn pinned(&self, wall: &str) -> Result<Vec<Arc<Msg>>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let guard = self.base.read().unwrap();
+        Ok(guard.msgs.iter()
+            .rev()
+            .filter(|msg| *msg.wall == *wall && msg.deleted_at.is_none() && !msg.is_expired(now) && msg.is_pinned(now))
+            .cloned()
+            .collect()
+        )
+    }
+
+    async fn set_banned(&self, ip: &str, banned: bool) -> Result<bool> {
+        let at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        Ok(self.ban_at(ip, banned.then_some(at)))
+    }
+
+    async fn is_banned(&self, ip: &str) -> Result<bool> {
+        Ok(self.base.read().unwrap().bans.contains_key(ip))
+    }
+
+    async fn banned_ips(&self) -> Result<Vec<Ban>> {
+        let guard = self.base.read().unwrap();
+        let mut bans: Vec<_> = guard.bans.iter()
+            .map(|(ip, banned_at)| Ban { ip: ip.clone(), banned_at: *banned_at })
+            .collect();
+        bans.sort_by_key(|ban| ban.banned_at);
+        Ok(bans)
+    }
+
+    async fn search(&self, wall: &str, query: &str, count: GetMsgs, limit: u32) -> Result<Vec<SearchHit>> {
+        let query: Vec<_> = search::words(query).collect();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let guard = self.base.read().unwrap();
+        let ids = guard.index.find(&query);
+        let shown = |id: &u32| guard.find(*id).filter(|msg| *msg.wall == *wall && !msg.is_expired(now));
+        let mut page: Vec<_> = match count {
+            Latest => ids.iter().rev().filter_map(shown).take(limit as usize).collect(),
+            Before(before) => ids.range(..u32::try_from(before).unwrap_or(u32::MAX))
+                .rev().filter_map(shown).take(limit as usize).collect(),
+            After(after) => ids.range(u32::try_from(after).unwrap_or(u32::MAX).saturating_add(1)..)
+                .filter_map(shown).take(limit as usize).collect(),
+        };
+        // newest first whichever way the page went
+        page.sort_by_key(|msg| std::cmp::Reverse(msg.id));
+        let mut hits: Vec<_> = page.into_iter()
+            .map(|msg| {
+                let marked = search::mark(&msg.content, &query);
+                let score = marked.matches(search::MARK_START).count();
+                (score, SearchHit { msg: msg.clone(), snippet: search::snippet_html(&marked) })
+            })
+            .collect();
+        // stable, so equally relevant ones stay newest first
+        hits.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        Ok(hits.into_iter().map(|(_, hit)| hit).collect())
+    }
+
+    async fn thread(&self, id: u32) -> Result<Vec<Arc<Msg>>> {
+        let guard = self.base.read().unwrap();
+        let start = guard.msgs.partition_point(|msg| msg.id < id);
+        let mut ids = HashSet::from([id]);
+        // replies always come after what they answer, so one pass finds them all
+        Ok(guard.msgs[start..].iter()
+            .filter(|msg| {
+                let in_thread = msg.id == id || msg.reply_to.is_some_and(|parent| ids.contains(&parent));
+                if in_thread {
+                    ids.insert(msg.id);
+                }
+                in_thread
+            })
+            .cloned()
+            .collect()
+        )
+    }
+
+    async fn react(&self, id: u32, emoji: &str, ip: &str, on: bool) -> Result<Option<Arc<Msg>>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let mut guard = self.base.write().unwrap();
+        if guard.find_live(id, now).is_none() {
+            return Ok(None);
+        }
+        let by_emoji = guard.reactions.entry(id).or_default();
+        let ips = by_emoji.entry(Arc::from(emoji)).or_default();
+        if on {
+            ips.insert(Arc::from(ip));
+        } else {
+            ips.remove(ip);
+        }
+        let count = ips.len() as u32;
+        if count == 0 {
+            by_emoji.remove(emoji);
+            if by_emoji.is_empty() {
+                guard.reactions.remove(&id);
             }
         }
+
+        let Some(msg) = guard.find_live_mut(id, now) else {
+            return Ok(None);
+        };
+        if count == 0 {
+            msg.reactions.remove(emoji);
+        } else {
+            msg.reactions.insert(Arc::from(emoji), count);
+        }
+        Ok(guard.find(id).cloned())
     }
 
-    async fn send_msg(&self, msg: ReceiveMsg) -> Result<()> {
+    async fn export(&self, wall: Option<&str>, after: u32, limit: u32) -> Result<Vec<ExportedMsg>> {
+        let guard = self.base.read().unwrap();
+        let start = guard.msgs.partition_point(|msg| msg.id <= after);
+        Ok(guard.msgs[start..].iter()
+            .filter(|msg| wall.is_none_or(|wall| *msg.wall == *wall))
+            .take(limit as usize)
+            .map(|msg| ExportedMsg {
+                msg: msg.as_ref().clone(),
+                meta: guard.meta.get(&msg.id).cloned().unwrap_or_default(),
+                reacted: guard.reactions.get(&msg.id)
+                    .into_iter()
+                    .flatten()
+                    .map(|(emoji, ips)| {
+                        let mut ips: Vec<_> = ips.iter().cloned().collect();
+                        ips.sort();
+                        (emoji.clone(), ips)
+                    })
+                    .collect(),
+            })
+            .collect()
+        )
+    }
+
+    async fn import(&self, msgs: Vec<ExportedMsg>) -> Result<()> {
         let mut guard = self.base.write().unwrap();
-        let id = guard.len() as u32 + 1;
-        guard.push(Arc::new(Msg {
-            id,
-            author: msg.author,
-            content: msg.content,
-            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
-        }));
+        guard.check_import(&msgs)?;
+
+        for ExportedMsg { msg, meta, reacted } in msgs {
+            let mut msg = Arc::new(msg);
+            let reactions: HashMap<_, HashSet<_>> = reacted.into_iter()
+                .filter(|(_, ips)| !ips.is_empty())
+                .map(|(emoji, ips)| (emoji, ips.into_iter().collect()))
+                .collect();
+            Arc::make_mut(&mut msg).reactions = reactions.iter()
+                .map(|(emoji, ips)| (emoji.clone(), ips.len() as u32))
+                .collect();
+            if !reactions.is_empty() {
+                guard.reactions.insert(msg.id, reactions);
+            }
+            if msg.deleted_at.is_none() {
+                guard.index.insert(&msg);
+            }
+            guard.meta.insert(msg.id, meta);
+            guard.msgs.push(msg);
+        }
+        guard.msgs.sort_by_key(|msg| msg.id);
+        guard.last_id = guard.last_id.max(guard.msgs.last().map_or(0, |msg| msg.id));
         Ok(())
     }
 
-    async fn last_msg(&self) -> Result<u32> {
+    async fn prunable(&self, wall: &str, sent_before: Option<u64>, keep: Option<u32>) -> Result<u32> {
         let guard = self.base.read().unwrap();
-        Ok(guard.len() as u32)
+        let mut on_wall = guard.msgs.iter().rev().filter(|msg| *msg.wall == *wall);
+        let too_old = sent_before
+            .and_then(|before| on_wall.clone().find(|msg| msg.timestamp < before))
+            .map_or(0, |msg| msg.id);
+        let too_many = keep
+            .and_then(|keep| on_wall.nth(keep as usize))
+            .map_or(0, |msg| msg.id);
+        Ok(too_old.max(too_many))
+    }
+
+    async fn prune(&self, wall: &str, up_to: u32) -> Result<u64> {
+        let mut guard = self.base.write().unwrap();
+        let end = guard.msgs.partition_point(|msg| msg.id <= up_to);
+        let ids = guard.msgs[..end].iter()
+            .filter(|msg| *msg.wall == *wall)
+            .map(|msg| msg.id)
+            .collect();
+        Ok(guard.remove(&ids).len() as u64)
+    }
+
+    async fn remove_expired(&self, now: u64) -> Result<Vec<Arc<Msg>>> {
+        let ids = self.expired(now).into_iter().collect();
+        Ok(self.base.write().unwrap().remove(&ids))
     }
-}
\ No newline at end of file
+
+    async fn pending_deliveries(&self) -> Result<Vec<Delivery>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let guard = self.base.read().unwrap();
+        Ok(guard.pending.iter()
+            .filter_map(|(id, integration)| Some(Delivery {
+                msg: guard.find_live(*id, now)?.clone(),
+                integration: integration.clone(),
+            }))
+            .collect()
+        )
+    }
+
+    async fn delivered(&self, id: u32, integration: &str) -> Result<()> {
+        self.deliver(id, integration);
+        Ok(())
+    }
+}
@@ -1,24 +1,163 @@
-#[cfg(not(feature = "sqlite_db"))]
 pub mod mock;
+pub mod journal;
+pub mod search;
+pub mod dump;
+pub mod retention;
 #[cfg(feature = "sqlite_db")]
 pub mod sqlite;
+#[cfg(feature = "postgres_db")]
+pub mod postgres;
+#[cfg(any(feature = "sqlite_db", feature = "postgres_db"))]
+pub mod migrations;
+#[cfg(any(feature = "sqlite_db", feature = "postgres_db"))]
+mod orm;
+#[cfg(test)]
+mod conformance;
 
+use std::collections::BTreeMap;
 use std::sync::Arc;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
+use search::SearchHit;
 
-#[derive(Debug, Clone, Serialize)]
+/// The wall everything lands on unless told otherwise, and that messages
+/// stored before there were several walls belong to.
+pub const DEFAULT_WALL: &str = "main";
+
+fn default_wall() -> Arc<str> {
+    Arc::from(DEFAULT_WALL)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Msg {
-    id: u32,
-    author: Arc<str>,
-    content: Arc<str>,
-    timestamp: u64,
+    pub id: u32,
+    /// ids are unique across walls, so a message is found by id alone
+    #[serde(default = "default_wall")]
+    pub wall: Arc<str>,
+    pub author: Arc<str>,
+    pub content: Arc<str>,
+    pub timestamp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edited_at: Option<u64>,
+    /// id of the message this one answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<u32>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    /// when the pin runs out, after that the message no longer counts as pinned
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pinned_until: Option<u64>,
+    /// how many people reacted with each emoji
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub reactions: BTreeMap<Arc<str>, u32>,
+    /// set once the message is deleted, its tombstone keeps the id taken
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<u64>,
+    /// when an ephemeral message stops showing and gets removed for good
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ReceiveMsg {
+    /// taken from the route, not the body
+    #[serde(skip, default = "default_wall")]
+    pub wall: Arc<str>,
     pub author: Arc<str>,
     pub content: Arc<str>,
+    /// has to be a live message
+    #[serde(default)]
+    pub reply_to: Option<u32>,
+    /// seconds until the message expires, none keeps it
+    #[serde(default)]
+    pub ttl: Option<u64>,
+    /// names of the integrations it is to be posted to, stored along with it
+    /// until each one has it
+    #[serde(skip)]
+    pub deliver_to: Vec<Arc<str>>,
+    /// filled in by the server, never taken from clients
+    #[serde(skip)]
+    pub meta: MsgMeta,
+}
+
+/// What is stored about a message besides the message itself, never shown on the wall.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MsgMeta {
+    /// hash of the secret that lets the author delete or edit it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delete_token_hash: Option<Arc<str>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_ip: Option<Arc<str>>,
+}
+
+/// Everything about a message, deleted ones included, for moderators.
+#[derive(Debug, Clone, Serialize)]
+pub struct RawMsg {
+    #[serde(flatten)]
+    pub msg: Arc<Msg>,
+    pub client_ip: Option<Arc<str>>,
+}
+
+/// A message with everything stored about it, as dumps carry it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedMsg {
+    #[serde(flatten)]
+    pub msg: Msg,
+    #[serde(flatten)]
+    pub meta: MsgMeta,
+    /// ips behind each reaction, the counts in `msg` follow from these on import
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub reacted: BTreeMap<Arc<str>, Vec<Arc<str>>>,
+}
+
+/// What `import` fails with when an id is taken, by the wall or earlier in the same dump.
+#[derive(Debug)]
+pub struct IdTaken(pub u32);
+
+impl std::fmt::Display for IdTaken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Message {} already exists", self.0)
+    }
+}
+
+impl std::error::Error for IdTaken {}
+
+/// A message still to be posted to one of its wall's integrations.
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    pub msg: Arc<Msg>,
+    pub integration: Arc<str>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Ban {
+    pub ip: Arc<str>,
+    pub banned_at: u64,
+}
+
+impl Msg {
+    /// What is left to show of a deleted message, just enough to keep its place in a thread.
+    pub fn tombstone(&self) -> Msg {
+        Msg {
+            author: Arc::from(""),
+            content: Arc::from(""),
+            edited_at: None,
+            pinned: false,
+            pinned_until: None,
+            reactions: BTreeMap::new(),
+            ..self.clone()
+        }
+    }
+
+    /// An ephemeral message past its time at unix time `now`, whether or not it is removed yet.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+
+    /// Pinned and the pin not yet run out at unix time `now`.
+    pub fn is_pinned(&self, now: u64) -> bool {
+        self.pinned && self.pinned_until.is_none_or(|until| until > now)
+    }
 }
 
 impl ReceiveMsg {
@@ -32,19 +171,132 @@ impl ReceiveMsg {
         if self.content.chars().count() > 250 {
             return Err(anyhow::anyhow!("Content too long"));
         }
+        // nothing but line breaks and tabs, the others could pass for search marks
+        if self.author.chars().any(char::is_control)
+            || self.content.chars().any(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t')) {
+            return Err(anyhow::anyhow!("Invalid characters"));
+        }
         Ok(())
     }
 }
 
+/// Which page of a wall to read. Every backend returns at most `limit`
+/// messages, newest first, skipping deleted ones.
 #[derive(Debug, Clone)]
 pub enum GetMsgs {
+    /// the newest messages of all
+    Latest,
+    /// the newest messages with an id below this one, to walk back from it
     Before(usize),
+    /// the oldest messages with an id above this one, to walk forward from it
     After(usize),
 }
 
+/// Narrows down what `get_msgs` returns, every field that is set must match.
+#[derive(Debug, Clone, Default)]
+pub struct MsgFilter {
+    /// exact author name
+    pub author: Option<Arc<str>>,
+    /// sent at or after this unix time
+    pub since: Option<u64>,
+    /// sent at or before this unix time
+    pub until: Option<u64>,
+    /// case-sensitive substring of the content
+    pub contains: Option<Arc<str>>,
+}
+
+impl MsgFilter {
+    pub fn matches(&self, msg: &Msg) -> bool {
+        self.author.as_ref().is_none_or(|author| *author == msg.author)
+            && self.since.is_none_or(|since| msg.timestamp >= since)
+            && self.until.is_none_or(|until| msg.timestamp <= until)
+            && self.contains.as_ref().is_none_or(|part| msg.content.contains(part.as_ref()))
+    }
+}
+
 #[async_trait::async_trait]
-pub trait Database: Clone + Send + Sync + 'static {
-    async fn get_msgs(&self, count: GetMsgs, limit: u32) -> Result<Vec<Arc<Msg>>>;
-    async fn send_msg(&self, msg: ReceiveMsg) -> Result<()>;
-    async fn last_msg(&self) -> Result<u32>;
-}
\ No newline at end of file
+pub trait Database: Send + Sync {
+    /// Expired messages are skipped like deleted ones.
+    async fn get_msgs(&self, wall: &str, count: GetMsgs, filter: &MsgFilter, limit: u32) -> Result<Vec<Arc<Msg>>>;
+    /// Stores the message along with its pending deliveries, all or nothing.
+    async fn send_msg(&self, msg: ReceiveMsg) -> Result<Arc<Msg>>;
+    /// Id of the newest message on `wall`, deleted ones included.
+    async fn last_msg(&self, wall: &str) -> Result<u32>;
+    /// Leaves a tombstone in place of the message, false if there was no live one.
+    async fn delete_msg(&self, id: u32) -> Result<bool>;
+    /// A live message by its id, live being neither deleted nor expired.
+    async fn get_msg(&self, id: u32) -> Result<Option<Arc<Msg>>>;
+    /// Hash of the delete token of a live message, if it was sent with one.
+    async fn delete_token_hash(&self, id: u32) -> Result<Option<Arc<str>>>;
+    /// Replaces the content of a live message, `None` if there is none.
+    async fn edit_msg(&self, id: u32, content: Arc<str>) -> Result<Option<Arc<Msg>>>;
+    /// A message with its metadata, even if deleted.
+    async fn raw_msg(&self, id: u32) -> Result<Option<RawMsg>>;
+    /// Pins a live message until the given unix time or for good, or unpins it
+    /// when `pinned` is false. `None` if there is no such message.
+    async fn set_pinned(&self, id: u32, pinned: bool, until: Option<u64>) -> Result<Option<Arc<Msg>>>;
+    /// Live messages on `wall` that are pinned right now, newest first.
+    async fn pinned(&self, wall: &str) -> Result<Vec<Arc<Msg>>>;
+    /// Bans or unbans an ip, false if it already was in that state.
+    async fn set_banned(&self, ip: &str, banned: bool) -> Result<bool>;
+    async fn is_banned(&self, ip: &str) -> Result<bool>;
+    async fn banned_ips(&self) -> Result<Vec<Ban>>;
+    /// Live, unexpired messages on `wall` having every word of `query`, paged like `get_msgs`,
+    /// but each page ordered by relevance.
+    async fn search(&self, wall: &str, query: &str, count: GetMsgs, limit: u32) -> Result<Vec<SearchHit>>;
+    /// Message `id` and every reply under it, tombstones included, ordered by id.
+    /// Empty if there is no such message at all.
+    async fn thread(&self, id: u32) -> Result<Vec<Arc<Msg>>>;
+    /// Adds or takes back the `emoji` reaction of `ip` on a live message,
+    /// `None` if there is none. Reacting twice counts once.
+    async fn react(&self, id: u32, emoji: &str, ip: &str, on: bool) -> Result<Option<Arc<Msg>>>;
+    /// Up to `limit` messages with an id above `after`, oldest first, tombstones
+    /// and metadata included. Every wall's unless `wall` is set.
+    async fn export(&self, wall: Option<&str>, after: u32, limit: u32) -> Result<Vec<ExportedMsg>>;
+    /// Stores dumped messages as they are, ids and timestamps included.
+    /// Stores none of them if any id is taken already.
+    async fn import(&self, msgs: Vec<ExportedMsg>) -> Result<()>;
+    /// Id of the newest message on `wall` sent before unix time `sent_before`, or with
+    /// more than `keep` newer ones there, tombstones counted. 0 if there is none.
+    async fn prunable(&self, wall: &str, sent_before: Option<u64>, keep: Option<u32>) -> Result<u32>;
+    /// Removes every message on `wall` with an id up to `up_to` for good, with its
+    /// reactions and metadata, replies left behind stop pointing at them. Their ids
+    /// stay taken. Returns how many went.
+    async fn prune(&self, wall: &str, up_to: u32) -> Result<u64>;
+    /// Removes every message expired at unix time `now` for good, tombstones included,
+    /// the way `prune` does. Returns what went, oldest first.
+    async fn remove_expired(&self, now: u64) -> Result<Vec<Arc<Msg>>>;
+    /// Deliveries of live messages not yet marked done, oldest message first.
+    async fn pending_deliveries(&self) -> Result<Vec<Delivery>>;
+    /// Marks posting message `id` to `integration` done.
+    async fn delivered(&self, id: u32, integration: &str) -> Result<()>;
+}
+
+/// Connection pool settings for the sea-orm backends.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(any(feature = "sqlite_db", feature = "postgres_db")), allow(dead_code))]
+pub struct PoolConfig {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout_secs: u64,
+}
+
+/// Opens the backend selected by `url`: `memory://`, `journal://<path>`, `sqlite://<path>` or `postgres://...`.
+#[cfg_attr(not(any(feature = "sqlite_db", feature = "postgres_db")), allow(unused_variables))]
+pub async fn connect(url: &str, pool: &PoolConfig) -> Result<Arc<dyn Database>> {
+    match url.split_once("://") {
+        Some(("memory", _)) => Ok(Arc::new(mock::MockBase::new())),
+        Some(("journal", location)) => Ok(Arc::new(journal::Journal::open(location)?)),
+        #[cfg(feature = "sqlite_db")]
+        Some(("sqlite", path)) => Ok(Arc::new(sqlite::Sqlite::new(path.to_string(), pool).await?)),
+        #[cfg(feature = "postgres_db")]
+        Some(("postgres" | "postgresql", _)) => Ok(Arc::new(postgres::Postgres::new(url.to_string(), pool).await?)),
+        Some(_) => Err(anyhow!("Unsupported database url: {} (not compiled in?)", url)),
+        None => Err(anyhow!("Invalid database url: {}", url)),
+    }
+}
+
+/// Brings the schema of the backend selected by `url` up to date, backends migrate on connect.
+pub async fn migrate(url: &str, pool: &PoolConfig) -> Result<()> {
+    connect(url, pool).await.map(|_| ())
+}
@@ -0,0 +1,572 @@
+use sea_orm::{ActiveModelTrait, ColumnTrait, Set};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use sea_orm::sea_query::{Alias, Expr, Func, OnConflict};
+use sea_orm::{Condition, ConnectOptions, ConnectionTrait, DatabaseConnection, DatabaseTransaction, DbBackend, EntityTrait, FromQueryResult, QueryFilter, QueryOrder, QuerySelect, QueryTrait, Statement, TransactionTrait};
+use sea_orm::sea_query::Order;
+use anyhow::Result;
+use crate::database::{Ban, Delivery, ExportedMsg, GetMsgs, IdTaken, Msg, MsgFilter, MsgMeta, PoolConfig, RawMsg, ReceiveMsg};
+use crate::database::GetMsgs::{After, Before, Latest};
+use crate::database::search::{snippet_html, SearchHit};
+use crate::entities::{ban, delivery, msg, reaction};
+use ban::Entity as Bans;
+use delivery::Entity as Deliveries;
+use reaction::Entity as Reactions;
+use msg::Entity as Messages;
+
+// Queries shared by the sea-orm backends, they only differ in how they connect and migrate.
+
+// rows per INSERT on import, well below what either backend takes in bound parameters
+const IMPORT_BATCH: usize = 500;
+
+pub fn connect_options(url: String, pool: &PoolConfig) -> ConnectOptions {
+    let mut options = ConnectOptions::new(url);
+    options
+        .max_connections(pool.max_connections)
+        .min_connections(pool.min_connections)
+        .acquire_timeout(Duration::from_secs(pool.acquire_timeout_secs));
+    options
+}
+
+impl From<&msg::Model> for Msg {
+    fn from(msg: &msg::Model) -> Self {
+        Self {
+            id: msg.id as u32,
+            wall: Arc::from(msg.wall.as_str()),
+            author: Arc::from(msg.author.as_str()),
+            content: Arc::from(msg.content.as_str()),
+            timestamp: msg.timestamp.unsigned_abs(),
+            edited_at: msg.edited_at.map(i64::unsigned_abs),
+            reply_to: msg.reply_to.map(|id| id as u32),
+            pinned: msg.pinned,
+            pinned_until: msg.pinned_until.map(i64::unsigned_abs),
+            reactions: BTreeMap::new(),
+            deleted_at: msg.deleted_at.map(i64::unsigned_abs),
+            expires_at: msg.expires_at.map(i64::unsigned_abs),
+        }
+    }   
+}
+
+#[derive(FromQueryResult)]
+struct ReactionCount {
+    msg_id: i32,
+    emoji: String,
+    count: i64,
+}
+
+/// Not an ephemeral message that has run out at unix time `now`.
+fn unexpired(now: u64) -> Condition {
+    Condition::any()
+        .add(msg::Column::ExpiresAt.is_null())
+        .add(msg::Column::ExpiresAt.gt(now as i64))
+}
+
+/// Turns rows into messages with their reaction counts, one query for all of them.
+async fn with_reactions(db: &DatabaseConnection, models: Vec<msg::Model>) -> Result<Vec<Arc<Msg>>> {
+    if models.is_empty() {
+        return Ok(Vec::new());
+    }
+    let counts = Reactions::find()
+        .select_only()
+        .column(reaction::Column::MsgId)
+        .column(reaction::Column::Emoji)
+        .column_as(Expr::col(reaction::Column::Ip).count(), "count")
+        .filter(reaction::Column::MsgId.is_in(models.iter().map(|msg| msg.id)))
+        .group_by(reaction::Column::MsgId)
+        .group_by(reaction::Column::Emoji)
+        .into_model::<ReactionCount>()
+        .all(db)
+        .await?;
+    let mut by_msg: HashMap<i32, BTreeMap<Arc<str>, u32>> = HashMap::new();
+    for count in counts {
+        by_msg.entry(count.msg_id).or_default().insert(Arc::from(count.emoji), count.count as u32);
+    }
+    Ok(
+        models.iter()
+            .map(|model| {
+                let mut msg: Msg = model.into();
+                msg.reactions = by_msg.remove(&model.id).unwrap_or_default();
+                Arc::new(msg)
+            })
+            .collect()
+    )
+}
+
+async fn with_reaction(db: &DatabaseConnection, model: Option<msg::Model>) -> Result<Option<Arc<Msg>>> {
+    Ok(with_reactions(db, model.into_iter().collect()).await?.pop())
+}
+
+pub async fn get_msgs(db: &DatabaseConnection, wall: &str, count: GetMsgs, filter: &MsgFilter, limit: u32) -> Result<Vec<Arc<Msg>>> {
+    tracing::info!("get_msgs: {} {:?}, {:?}, limit: {}", wall, count, filter, limit);
+    let (cursor, order) = match count {
+        Latest => (None, Order::Desc),
+        Before(before) => (Some(msg::Column::Id.lt(before as i64)), Order::Desc),
+        After(after) => (Some(msg::Column::Id.gt(after as i64)), Order::Asc),
+    };
+    // same arguments on both, a plain case-sensitive substring search unlike LIKE
+    let position = match db.get_database_backend() {
+        DbBackend::Postgres => "strpos",
+        _ => "instr",
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut msgs =
+        Messages::find()
+            .filter(msg::Column::Wall.eq(wall))
+            .apply_if(cursor, |query, cursor| query.filter(cursor))
+            .filter(msg::Column::DeletedAt.is_null())
+            .filter(unexpired(now))
+            .apply_if(filter.author.as_deref(), |query, author| query.filter(msg::Column::Author.eq(author)))
+            .apply_if(filter.since, |query, since| query.filter(msg::Column::Timestamp.gte(since as i64)))
+            .apply_if(filter.until, |query, until| query.filter(msg::Column::Timestamp.lte(until as i64)))
+            .apply_if(filter.contains.as_deref(), |query, part| query.filter(
+                Expr::expr(Func::cust(Alias::new(position)).arg(Expr::col(msg::Column::Content)).arg(part)).gt(0)
+            ))
+            .order_by(msg::Column::Id, order)
+            .limit(limit as u64)
+            .all(db)
+            .await?;
+    if matches!(count, After(_)) {
+        msgs.reverse();
+    }
+    with_reactions(db, msgs).await
+}
+
+pub async fn send_msg(db: &DatabaseConnection, msg: ReceiveMsg) -> Result<Arc<Msg>> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let model = msg::ActiveModel {
+        wall: Set(msg.wall.to_string()),
+        author: Set(msg.author.to_string()),
+        content: Set(msg.content.to_string()),
+        timestamp: Set(timestamp as i64),
+        expires_at: Set(msg.ttl.map(|ttl| (timestamp + ttl) as i64)),
+        delete_token_hash: Set(msg.meta.delete_token_hash.map(|hash| hash.to_string())),
+        client_ip: Set(msg.meta.client_ip.map(|ip| ip.to_string())),
+        reply_to: Set(msg.reply_to.map(|id| id as i32)),
+        ..Default::default() };
+    let txn = db.begin().await?;
+    let model = model.insert(&txn).await?;
+    if !msg.deliver_to.is_empty() {
+        Deliveries::insert_many(msg.deliver_to.iter().map(|integration| delivery::ActiveModel {
+            msg_id: Set(model.id),
+            integration: Set(integration.to_string()),
+            delivered_at: Set(None),
+        }))
+            .exec_without_returning(&txn)
+            .await?;
+    }
+    txn.commit().await?;
+    Ok(Arc::new((&model).into()))
+}
+
+pub async fn last_msg(db: &DatabaseConnection, wall: &str) -> Result<u32> {
+    Ok(
+        Messages::find()
+            .filter(msg::Column::Wall.eq(wall))
+            .order_by_desc(msg::Column::Id)
+            .one(db)
+            .await?
+            .map(|msg| msg.id as u32)
+            .unwrap_or(
+                0
+            )
+    )
+}
+
+pub async fn delete_msg(db: &DatabaseConnection, id: u32) -> Result<bool> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let deleted = Messages::update_many()
+        .col_expr(msg::Column::DeletedAt, Expr::value(now as i64))
+        .filter(msg::Column::Id.eq(id as i64))
+        .filter(msg::Column::DeletedAt.is_null())
+        .filter(unexpired(now))
+        .exec(db)
+        .await?;
+    Ok(deleted.rows_affected > 0)
+}
+
+/// Neither deleted nor expired.
+async fn find_live(db: &DatabaseConnection, id: u32) -> Result<Option<msg::Model>> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    Ok(
+        Messages::find_by_id(id as i32)
+            .filter(msg::Column::DeletedAt.is_null())
+            .filter(unexpired(now))
+            .one(db)
+            .await?
+    )
+}
+
+pub async fn get_msg(db: &DatabaseConnection, id: u32) -> Result<Option<Arc<Msg>>> {
+    with_reaction(db, find_live(db, id).await?).await
+}
+
+pub async fn delete_token_hash(db: &DatabaseConnection, id: u32) -> Result<Option<Arc<str>>> {
+    Ok(find_live(db, id).await?.and_then(|msg| msg.delete_token_hash).map(Arc::from))
+}
+
+pub async fn edit_msg(db: &DatabaseConnection, id: u32, content: Arc<str>) -> Result<Option<Arc<Msg>>> {
+    let Some(model) = find_live(db, id).await? else {
+        return Ok(None);
+    };
+    let mut model: msg::ActiveModel = model.into();
+    model.content = Set(content.to_string());
+    model.edited_at = Set(Some(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64));
+    let model = model.update(db).await?;
+    with_reaction(db, Some(model)).await
+}
+
+pub async fn raw_msg(db: &DatabaseConnection, id: u32) -> Result<Option<RawMsg>> {
+    let Some(model) = Messages::find_by_id(id as i32).one(db).await? else {
+        return Ok(None);
+    };
+    let client_ip = model.client_ip.clone().map(Arc::from);
+    Ok(with_reaction(db, Some(model)).await?.map(|msg| RawMsg { msg, client_ip }))
+}
+
+pub async fn set_pinned(db: &DatabaseConnection, id: u32, pinned: bool, until: Option<u64>) -> Result<Option<Arc<Msg>>> {
+    let Some(model) = find_live(db, id).await? else {
+        return Ok(None);
+    };
+    let mut model: msg::ActiveModel = model.into();
+    model.pinned = Set(pinned);
+    model.pinned_until = Set(until.filter(|_| pinned).map(|until| until as i64));
+    let model = model.update(db).await?;
+    with_reaction(db, Some(model)).await
+}
+
+pub async fn pinned(db: &DatabaseConnection, wall: &str) -> Result<Vec<Arc<Msg>>> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let msgs = Messages::find()
+        .filter(msg::Column::Wall.eq(wall))
+        .filter(msg::Column::Pinned.eq(true))
+        .filter(msg::Column::DeletedAt.is_null())
+        .filter(unexpired(now as u64))
+        .filter(msg::Column::PinnedUntil.is_null().or(msg::Column::PinnedUntil.gt(now)))
+        .order_by_desc(msg::Column::Id)
+        .all(db)
+        .await?;
+    with_reactions(db, msgs).await
+}
+
+pub async fn set_banned(db: &DatabaseConnection, ip: &str, banned: bool) -> Result<bool> {
+    if !banned {
+        return Ok(Bans::delete_by_id(ip.to_string()).exec(db).await?.rows_affected > 0);
+    }
+    let inserted = Bans::insert(ban::ActiveModel {
+        ip: Set(ip.to_string()),
+        banned_at: Set(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64),
+    })
+        .on_conflict(OnConflict::column(ban::Column::Ip).do_nothing().to_owned())
+        .exec_without_returning(db)
+        .await?;
+    Ok(inserted > 0)
+}
+
+pub async fn is_banned(db: &DatabaseConnection, ip: &str) -> Result<bool> {
+    Ok(Bans::find_by_id(ip.to_string()).one(db).await?.is_some())
+}
+
+pub async fn banned_ips(db: &DatabaseConnection) -> Result<Vec<Ban>> {
+    Ok(
+        Bans::find()
+            .order_by_asc(ban::Column::BannedAt)
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|ban| Ban { ip: Arc::from(ban.ip), banned_at: ban.banned_at.unsigned_abs() })
+            .collect()
+    )
+}
+
+pub async fn thread(db: &DatabaseConnection, id: u32) -> Result<Vec<Arc<Msg>>> {
+    let Some(root) = Messages::find_by_id(id as i32).one(db).await? else {
+        return Ok(Vec::new());
+    };
+    // a level of replies per query, threads are never deep enough for that to matter
+    let mut frontier = vec![root.id];
+    let mut thread = vec![root];
+    while !frontier.is_empty() {
+        let replies = Messages::find()
+            .filter(msg::Column::ReplyTo.is_in(frontier))
+            .all(db)
+            .await?;
+        frontier = replies.iter().map(|msg| msg.id).collect();
+        thread.extend(replies);
+    }
+    thread.sort_by_key(|msg| msg.id);
+    with_reactions(db, thread).await
+}
+
+pub async fn react(db: &DatabaseConnection, id: u32, emoji: &str, ip: &str, on: bool) -> Result<Option<Arc<Msg>>> {
+    let Some(model) = find_live(db, id).await? else {
+        return Ok(None);
+    };
+    if on {
+        Reactions::insert(reaction::ActiveModel {
+            msg_id: Set(model.id),
+            emoji: Set(emoji.to_string()),
+            ip: Set(ip.to_string()),
+        })
+            .on_conflict(OnConflict::columns([reaction::Column::MsgId, reaction::Column::Emoji, reaction::Column::Ip])
+                .do_nothing()
+                .to_owned())
+            .exec_without_returning(db)
+            .await?;
+    } else {
+        Reactions::delete_by_id((model.id, emoji.to_string(), ip.to_string()))
+            .exec(db)
+            .await?;
+    }
+    with_reaction(db, Some(model)).await
+}
+
+pub async fn export(db: &DatabaseConnection, wall: Option<&str>, after: u32, limit: u32) -> Result<Vec<ExportedMsg>> {
+    let models = Messages::find()
+        .filter(msg::Column::Id.gt(after as i64))
+        .apply_if(wall, |query, wall| query.filter(msg::Column::Wall.eq(wall)))
+        .order_by_asc(msg::Column::Id)
+        .limit(limit as u64)
+        .all(db)
+        .await?;
+    if models.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut reacted: HashMap<i32, BTreeMap<Arc<str>, Vec<Arc<str>>>> = HashMap::new();
+    let reactions = Reactions::find()
+        .filter(reaction::Column::MsgId.is_in(models.iter().map(|msg| msg.id)))
+        .order_by_asc(reaction::Column::Ip)
+        .all(db)
+        .await?;
+    for reaction in reactions {
+        reacted.entry(reaction.msg_id)
+            .or_default()
+            .entry(Arc::from(reaction.emoji))
+            .or_default()
+            .push(Arc::from(reaction.ip));
+    }
+    Ok(
+        models.into_iter()
+            .map(|model| {
+                let reacted = reacted.remove(&model.id).unwrap_or_default();
+                let mut msg: Msg = (&model).into();
+                msg.reactions = reacted.iter().map(|(emoji, ips)| (emoji.clone(), ips.len() as u32)).collect();
+                let meta = MsgMeta {
+                    delete_token_hash: model.delete_token_hash.map(Arc::from),
+                    client_ip: model.client_ip.map(Arc::from),
+                };
+                ExportedMsg { msg, meta, reacted }
+            })
+            .collect()
+    )
+}
+
+pub async fn import(db: &DatabaseConnection, mut msgs: Vec<ExportedMsg>) -> Result<()> {
+    // parents before their replies, or the reply_to foreign key fails
+    msgs.sort_by_key(|exported| exported.msg.id);
+    let mut ids = HashSet::new();
+    if let Some(twice) = msgs.iter().find(|exported| !ids.insert(exported.msg.id)) {
+        return Err(IdTaken(twice.msg.id).into());
+    }
+
+    let txn = db.begin().await?;
+    for batch in msgs.chunks(IMPORT_BATCH) {
+        let taken = Messages::find()
+            .filter(msg::Column::Id.is_in(batch.iter().map(|exported| exported.msg.id as i32)))
+            .one(&txn)
+            .await?;
+        if let Some(taken) = taken {
+            return Err(IdTaken(taken.id as u32).into());
+        }
+
+        let mut reactions = Vec::new();
+        let models = batch.iter().map(|ExportedMsg { msg, meta, reacted }| {
+            for (emoji, ips) in reacted {
+                // the primary key takes each ip once
+                for ip in ips.iter().collect::<BTreeSet<_>>() {
+                    reactions.push(reaction::ActiveModel {
+                        msg_id: Set(msg.id as i32),
+                        emoji: Set(emoji.to_string()),
+                        ip: Set(ip.to_string()),
+                    });
+                }
+            }
+            msg::ActiveModel {
+                id: Set(msg.id as i32),
+                wall: Set(msg.wall.to_string()),
+                author: Set(msg.author.to_string()),
+                content: Set(msg.content.to_string()),
+                timestamp: Set(msg.timestamp as i64),
+                deleted_at: Set(msg.deleted_at.map(|at| at as i64)),
+                edited_at: Set(msg.edited_at.map(|at| at as i64)),
+                delete_token_hash: Set(meta.delete_token_hash.as_ref().map(|hash| hash.to_string())),
+                client_ip: Set(meta.client_ip.as_ref().map(|ip| ip.to_string())),
+                pinned: Set(msg.pinned),
+                pinned_until: Set(msg.pinned_until.map(|until| until as i64)),
+                reply_to: Set(msg.reply_to.map(|id| id as i32)),
+                expires_at: Set(msg.expires_at.map(|at| at as i64)),
+            }
+        }).collect::<Vec<_>>();
+        Messages::insert_many(models).exec_without_returning(&txn).await?;
+        for reactions in reactions.chunks(IMPORT_BATCH) {
+            Reactions::insert_many(reactions.to_vec()).exec_without_returning(&txn).await?;
+        }
+    }
+    if db.get_database_backend() == DbBackend::Postgres && !msgs.is_empty() {
+        // explicit ids don't move the sequence, new messages would run into them
+        txn.execute_unprepared("SELECT setval(pg_get_serial_sequence('messages', 'id'), (SELECT MAX(id) FROM messages));").await?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
+pub async fn prunable(db: &DatabaseConnection, wall: &str, sent_before: Option<u64>, keep: Option<u32>) -> Result<u32> {
+    let newest = || Messages::find()
+        .filter(msg::Column::Wall.eq(wall))
+        .order_by_desc(msg::Column::Id);
+    let too_old = match sent_before {
+        Some(before) => newest()
+            .filter(msg::Column::Timestamp.lt(before as i64))
+            .one(db)
+            .await?,
+        None => None,
+    };
+    let too_many = match keep {
+        Some(keep) => newest()
+            .offset(keep as u64)
+            .one(db)
+            .await?,
+        None => None,
+    };
+    Ok(too_old.into_iter().chain(too_many).map(|msg| msg.id as u32).max().unwrap_or(0))
+}
+
+/// Deletes the messages matching `which` for good, along with their reactions and deliveries.
+async fn remove(txn: &DatabaseTransaction, which: Condition) -> Result<u64> {
+    let doomed = || Messages::find()
+        .select_only()
+        .column(msg::Column::Id)
+        .filter(which.clone())
+        .into_query();
+    // nothing may point at them anymore, or the foreign keys fail the delete
+    Messages::update_many()
+        .col_expr(msg::Column::ReplyTo, Expr::value(Option::<i32>::None))
+        .filter(msg::Column::ReplyTo.in_subquery(doomed()))
+        .exec(txn)
+        .await?;
+    Reactions::delete_many()
+        .filter(reaction::Column::MsgId.in_subquery(doomed()))
+        .exec(txn)
+        .await?;
+    Deliveries::delete_many()
+        .filter(delivery::Column::MsgId.in_subquery(doomed()))
+        .exec(txn)
+        .await?;
+    Ok(Messages::delete_many().filter(which).exec(txn).await?.rows_affected)
+}
+
+pub async fn prune(db: &DatabaseConnection, wall: &str, up_to: u32) -> Result<u64> {
+    let txn = db.begin().await?;
+    let pruned = remove(&txn, Condition::all()
+        .add(msg::Column::Wall.eq(wall))
+        .add(msg::Column::Id.lte(up_to as i64))
+    ).await?;
+    txn.commit().await?;
+    Ok(pruned)
+}
+
+pub async fn remove_expired(db: &DatabaseConnection, now: u64) -> Result<Vec<Arc<Msg>>> {
+    let txn = db.begin().await?;
+    let expired = Messages::find()
+        .filter(msg::Column::ExpiresAt.lte(now as i64))
+        .order_by_asc(msg::Column::Id)
+        .all(&txn)
+        .await?;
+    if expired.is_empty() {
+        return Ok(Vec::new());
+    }
+    remove(&txn, Condition::all().add(msg::Column::Id.is_in(expired.iter().map(|msg| msg.id)))).await?;
+    txn.commit().await?;
+    Ok(expired.iter().map(|model| Arc::new(model.into())).collect())
+}
+
+pub async fn pending_deliveries(db: &DatabaseConnection) -> Result<Vec<Delivery>> {
+    let pending = Deliveries::find()
+        .filter(delivery::Column::DeliveredAt.is_null())
+        .order_by_asc(delivery::Column::MsgId)
+        .order_by_asc(delivery::Column::Integration)
+        .all(db)
+        .await?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let msgs = Messages::find()
+        .filter(msg::Column::Id.is_in(pending.iter().map(|delivery| delivery.msg_id).collect::<BTreeSet<_>>()))
+        .filter(msg::Column::DeletedAt.is_null())
+        .filter(unexpired(now))
+        .all(db)
+        .await?;
+    let msgs: HashMap<i32, Arc<Msg>> = with_reactions(db, msgs)
+        .await?
+        .into_iter()
+        .map(|msg| (msg.id as i32, msg))
+        .collect();
+    Ok(
+        pending
+            .into_iter()
+            .filter_map(|delivery| Some(Delivery {
+                msg: msgs.get(&delivery.msg_id)?.clone(),
+                integration: Arc::from(delivery.integration),
+            }))
+            .collect()
+    )
+}
+
+pub async fn delivered(db: &DatabaseConnection, id: u32, integration: &str) -> Result<()> {
+    Deliveries::update_many()
+        .col_expr(delivery::Column::DeliveredAt, Expr::value(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64))
+        .filter(delivery::Column::MsgId.eq(id as i64))
+        .filter(delivery::Column::Integration.eq(integration))
+        .filter(delivery::Column::DeliveredAt.is_null())
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Runs a backend's own search query. `sql` gets the comparison the cursor needs and
+/// the order (`ASC` or `DESC`) to pick the page's messages by id in, and must take the match expression, the wall, the current unix time (to leave out expired
+/// messages), the cursor and the limit as parameters,
+/// returning message rows with a marked up `snippet`.
+pub async fn search(
+    db: &DatabaseConnection,
+    sql: fn(&str, &str) -> String,
+    terms: String,
+    wall: &str,
+    count: GetMsgs,
+    limit: u32,
+) -> Result<Vec<SearchHit>> {
+    let (cmp, cursor, order) = match count {
+        Latest => ("<", i64::MAX, "DESC"),
+        Before(before) => ("<", before as i64, "DESC"),
+        After(after) => (">", after as i64, "ASC"),
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let statement = Statement::from_sql_and_values(
+        db.get_database_backend(),
+        sql(cmp, order),
+        [terms.into(), wall.into(), (now as i64).into(), cursor.into(), (limit as i64).into()],
+    );
+    let mut msgs = Vec::new();
+    let mut snippets = Vec::new();
+    for row in db.query_all(statement).await? {
+        msgs.push(msg::Model::from_query_result(&row, "")?);
+        snippets.push(snippet_html(&row.try_get::<String>("", "snippet")?));
+    }
+    Ok(
+        with_reactions(db, msgs)
+            .await?
+            .into_iter()
+            .zip(snippets)
+            .map(|(msg, snippet)| SearchHit { msg, snippet })
+            .collect()
+    )
+}
@@ -0,0 +1,138 @@
+use std::sync::Arc;
+use sea_orm::{Database, DatabaseConnection};
+use anyhow::Result;
+use crate::database::{Database as TDatabase, Ban, Delivery, ExportedMsg, GetMsgs, Msg, MsgFilter, PoolConfig, RawMsg, ReceiveMsg};
+use crate::database::{migrations, orm, search};
+use crate::database::search::SearchHit;
+
+#[derive(Clone)]
+pub struct Postgres {
+    db: Arc<DatabaseConnection>,
+}
+
+impl Postgres {
+    pub async fn new(url: String, pool: &PoolConfig) -> Result<Self> {
+        let db = Database::connect(orm::connect_options(url, pool)).await?;
+        migrations::run(&db).await?;
+        Ok( Self { db: Arc::new(db) } )
+    }
+}
+
+// the matches next to the cursor, then the best ones first. Messages are short,
+// so the whole one is the snippet, shorter headlines also drop whatever looks like a tag.
+fn search_sql(cmp: &str, order: &str) -> String {
+    format!(
+        "SELECT * FROM (
+            SELECT messages.*,
+                ts_headline('simple', content, query,
+                    'HighlightAll=true, StartSel=' || chr(2) || ', StopSel=' || chr(3)) AS snippet,
+                ts_rank(to_tsvector('simple', content), query) AS rank
+            FROM messages, plainto_tsquery('simple', $1) AS query
+            WHERE to_tsvector('simple', content) @@ query AND wall = $2
+                AND (expires_at IS NULL OR expires_at > $3)
+                AND deleted_at IS NULL AND id {} $4
+            ORDER BY id {}
+            LIMIT $5
+        ) AS page ORDER BY rank DESC, id DESC;",
+        cmp, order,
+    )
+}
+
+#[async_trait::async_trait]
+impl TDatabase for Postgres {
+    async fn get_msgs(&self, wall: &str, count: GetMsgs, filter: &MsgFilter, limit: u32) -> Result<Vec<Arc<Msg>>> {
+        orm::get_msgs(&self.db, wall, count, filter, limit).await
+    }
+
+    async fn send_msg(&self, msg: ReceiveMsg) -> Result<Arc<Msg>> {
+        orm::send_msg(&self.db, msg).await
+    }
+
+    async fn last_msg(&self, wall: &str) -> Result<u32> {
+        orm::last_msg(&self.db, wall).await
+    }
+
+    async fn delete_msg(&self, id: u32) -> Result<bool> {
+        orm::delete_msg(&self.db, id).await
+    }
+
+    async fn get_msg(&self, id: u32) -> Result<Option<Arc<Msg>>> {
+        orm::get_msg(&self.db, id).await
+    }
+
+    async fn delete_token_hash(&self, id: u32) -> Result<Option<Arc<str>>> {
+        orm::delete_token_hash(&self.db, id).await
+    }
+
+    async fn edit_msg(&self, id: u32, content: Arc<str>) -> Result<Option<Arc<Msg>>> {
+        orm::edit_msg(&self.db, id, content).await
+    }
+
+    async fn raw_msg(&self, id: u32) -> Result<Option<RawMsg>> {
+        orm::raw_msg(&self.db, id).await
+    }
+
+    async fn set_pinned(&self, id: u32, pinned: bool, until: Option<u64>) -> Result<Option<Arc<Msg>>> {
+        orm::set_pinned(&self.db, id, pinned, until).await
+    }
+
+    async fn pinned(&self, wall: &str) -> Result<Vec<Arc<Msg>>> {
+        orm::pinned(&self.db, wall).await
+    }
+
+    async fn set_banned(&self, ip: &str, banned: bool) -> Result<bool> {
+        orm::set_banned(&self.db, ip, banned).await
+    }
+
+    async fn is_banned(&self, ip: &str) -> Result<bool> {
+        orm::is_banned(&self.db, ip).await
+    }
+
+    async fn banned_ips(&self) -> Result<Vec<Ban>> {
+        orm::banned_ips(&self.db).await
+    }
+
+    async fn search(&self, wall: &str, query: &str, count: GetMsgs, limit: u32) -> Result<Vec<SearchHit>> {
+        let terms = search::words(query).collect::<Vec<_>>().join(" ");
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+        orm::search(&self.db, search_sql, terms, wall, count, limit).await
+    }
+
+    async fn thread(&self, id: u32) -> Result<Vec<Arc<Msg>>> {
+        orm::thread(&self.db, id).await
+    }
+
+    async fn react(&self, id: u32, emoji: &str, ip: &str, on: bool) -> Result<Option<Arc<Msg>>> {
+        orm::react(&self.db, id, emoji, ip, on).await
+    }
+
+    async fn export(&self, wall: Option<&str>, after: u32, limit: u32) -> Result<Vec<ExportedMsg>> {
+        orm::export(&self.db, wall, after, limit).await
+    }
+
+    async fn import(&self, msgs: Vec<ExportedMsg>) -> Result<()> {
+        orm::import(&self.db, msgs).await
+    }
+
+    async fn prunable(&self, wall: &str, sent_before: Option<u64>, keep: Option<u32>) -> Result<u32> {
+        orm::prunable(&self.db, wall, sent_before, keep).await
+    }
+
+    async fn prune(&self, wall: &str, up_to: u32) -> Result<u64> {
+        orm::prune(&self.db, wall, up_to).await
+    }
+
+    async fn remove_expired(&self, now: u64) -> Result<Vec<Arc<Msg>>> {
+        orm::remove_expired(&self.db, now).await
+    }
+
+    async fn pending_deliveries(&self) -> Result<Vec<Delivery>> {
+        orm::pending_deliveries(&self.db).await
+    }
+
+    async fn delivered(&self, id: u32, integration: &str) -> Result<()> {
+        orm::delivered(&self.db, id, integration).await
+    }
+}
@@ -0,0 +1,66 @@
+//! Keeps walls from growing forever: what is too old or too much is pruned
+//! in the background, optionally archived to a dump first.
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::Result;
+use crate::database::{dump, Database};
+
+// messages archived per dump append
+const ARCHIVE_BATCH: u32 = 500;
+
+/// What is kept of each wall, at least one limit is set.
+#[derive(Debug, Clone)]
+pub struct Retention {
+    /// messages sent longer ago go
+    pub max_age_secs: Option<u64>,
+    /// the newest this many stay, tombstones counted
+    pub max_msgs: Option<u32>,
+    /// JSON Lines dump pruned messages are appended to, `wall import` takes it back
+    pub archive: Option<String>,
+    pub interval_secs: u64,
+}
+
+/// Prunes each of `walls` down to what `policy` keeps, returns how many messages went.
+pub async fn prune(db: &dyn Database, walls: &[String], policy: &Retention) -> Result<u64> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let sent_before = policy.max_age_secs.map(|age| now.saturating_sub(age));
+    let mut pruned = 0;
+    for wall in walls {
+        let up_to = db.prunable(wall, sent_before, policy.max_msgs).await?;
+        if up_to == 0 {
+            continue;
+        }
+        if let Some(archive) = &policy.archive {
+            let mut after = 0;
+            loop {
+                let batch: Vec<_> = db.export(Some(wall), after, ARCHIVE_BATCH).await?
+                    .into_iter()
+                    .take_while(|exported| exported.msg.id <= up_to)
+                    .collect();
+                let Some(last) = batch.last() else {
+                    break;
+                };
+                after = last.msg.id;
+                dump::append(archive, batch)?;
+            }
+        }
+        let count = db.prune(wall, up_to).await?;
+        tracing::info!("Pruned {} messages up to {} from wall {}", count, up_to, wall);
+        pruned += count;
+    }
+    Ok(pruned)
+}
+
+/// Prunes every `interval_secs`, starting right away, for as long as the server runs.
+pub async fn run(db: Arc<dyn Database>, walls: Vec<String>, policy: Retention) {
+    let mut interval = tokio::time::interval(Duration::from_secs(policy.interval_secs));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        match prune(db.as_ref(), &walls, &policy).await {
+            Ok(pruned) => tracing::info!("Retention run removed {} messages", pruned),
+            Err(e) => tracing::error!("Retention run failed: {}", e),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Full-text search bits every backend shares: how text is split into words,
+//! what a hit looks like and the index the in-memory backends use.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use serde::Serialize;
+use crate::database::Msg;
+use crate::utils::html::escape_html;
+
+// backends wrap matched words in these, `ReceiveMsg::check_valid` keeps them out of messages
+pub const MARK_START: char = '\u{2}';
+pub const MARK_END: char = '\u{3}';
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub msg: Arc<Msg>,
+    /// html escaped content with the matched words in `<mark>`
+    pub snippet: String,
+}
+
+/// Lowercased words of `text`, a message matches a query if it has all of the query's words.
+pub fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// Wraps every word of `content` that is one of `query` in `MARK_START` and `MARK_END`.
+pub fn mark(content: &str, query: &[String]) -> String {
+    let mut marked = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find(char::is_alphanumeric) {
+        let (between, from_word) = rest.split_at(start);
+        let end = from_word.find(|c: char| !c.is_alphanumeric()).unwrap_or(from_word.len());
+        let (word, after) = from_word.split_at(end);
+        marked.push_str(between);
+        if query.contains(&word.to_lowercase()) {
+            marked.push(MARK_START);
+            marked.push_str(word);
+            marked.push(MARK_END);
+        } else {
+            marked.push_str(word);
+        }
+        rest = after;
+    }
+    marked.push_str(rest);
+    marked
+}
+
+/// Turns text marked up by a backend into a snippet safe to show.
+pub fn snippet_html(marked: &str) -> String {
+    escape_html(marked)
+        .replace(MARK_START, "<mark>")
+        .replace(MARK_END, "</mark>")
+}
+
+/// Word to ids of the live messages that have it.
+#[derive(Default)]
+pub struct SearchIndex {
+    words: HashMap<String, BTreeSet<u32>>,
+}
+
+impl SearchIndex {
+    pub fn insert(&mut self, msg: &Msg) {
+        for word in words(&msg.content) {
+            self.words.entry(word).or_default().insert(msg.id);
+        }
+    }
+
+    pub fn remove(&mut self, msg: &Msg) {
+        for word in words(&msg.content) {
+            if let Some(ids) = self.words.get_mut(&word) {
+                ids.remove(&msg.id);
+                if ids.is_empty() {
+                    self.words.remove(&word);
+                }
+            }
+        }
+    }
+
+    /// Ids of messages that have every word of `query`, none for an empty one.
+    pub fn find(&self, query: &[String]) -> BTreeSet<u32> {
+        let mut sets = query.iter().map(|word| self.words.get(word));
+        let Some(Some(first)) = sets.next() else {
+            return BTreeSet::new();
+        };
+        let mut ids = first.clone();
+        for set in sets {
+            match set {
+                Some(set) => ids.retain(|id| set.contains(id)),
+                None => return BTreeSet::new(),
+            }
+        }
+        ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::{MsgMeta, ReceiveMsg};
+    use super::*;
+
+    fn receive(content: &str) -> ReceiveMsg {
+        ReceiveMsg {
+            wall: Arc::from("main"),
+            author: Arc::from("author"),
+            content: Arc::from(content),
+            reply_to: None,
+            ttl: None,
+            deliver_to: Vec::new(),
+            meta: MsgMeta::default(),
+        }
+    }
+
+    #[test]
+    fn marks_cant_be_posted() {
+        let marked = format!("{}fake{} mark", MARK_START, MARK_END);
+        assert!(receive(&marked).check_valid().is_err());
+        assert!(receive("a\u{0}b").check_valid().is_err());
+        assert!(receive("two\nlines\tand a tab").check_valid().is_ok());
+        // the only marks left in a snippet are those of the search
+        assert_eq!(snippet_html(&mark("fox <b>", &["fox".to_string()])), "<mark>fox</mark> &lt;b&gt;");
+    }
+}
@@ -1,12 +1,9 @@
-use sea_orm::{ActiveModelTrait, ColumnTrait, ConnectionTrait, DbBackend, Set};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
-use sea_orm::{Database, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
+use sea_orm::{Database, DatabaseConnection};
 use anyhow::Result;
-use crate::database::{Database as TDatabase, GetMsgs, Msg, ReceiveMsg};
-use crate::database::GetMsgs::{After, Before};
-use crate::entities::msg;
-use msg::Entity as Messages;
+use crate::database::{Database as TDatabase, Ban, Delivery, ExportedMsg, GetMsgs, Msg, MsgFilter, PoolConfig, RawMsg, ReceiveMsg};
+use crate::database::{migrations, orm, search};
+use crate::database::search::SearchHit;
 
 #[derive(Clone)]
 pub struct Sqlite {
@@ -14,109 +11,135 @@ pub struct Sqlite {
 }
 
 impl Sqlite {
-    pub async fn new(filename: String) -> Result<Self> {
+    pub async fn new(filename: String, pool: &PoolConfig) -> Result<Self> {
         let abs = std::env::current_dir()?.join(filename);
         let abs_str = abs.to_str().ok_or_else(|| anyhow::anyhow!("non-utf8 path"))?;
         
         let db = 
-            Database::connect(format!("sqlite://{}", abs_str))
+            Database::connect(orm::connect_options(format!("sqlite://{}?mode=rwc", abs_str), pool))
                 .await?;
-        db.execute(
-            sea_orm::Statement::from_string(
-                DbBackend::Sqlite,
-                "CREATE TABLE IF NOT EXISTS messages (id INTEGER PRIMARY KEY AUTOINCREMENT,
-                    author TEXT NOT NULL,
-                    content TEXT NOT NULL,
-                    timestamp INTEGER NOT NULL);".to_string()
-            )
-        ).await?;
+        migrations::run(&db).await?;
         Ok( Self { db: Arc::new(db) } )
     }
 }
 
-impl From<&msg::Model> for Msg {
-    fn from(msg: &msg::Model) -> Self {
-        Self {
-            id: msg.id,
-            author: Arc::from(msg.author.as_str()),
-            content: Arc::from(msg.content.as_str()),
-            timestamp: msg.timestamp.unsigned_abs()
-        }
-    }   
+// the matches next to the cursor, then the best ones (lowest bm25) first
+fn search_sql(cmp: &str, order: &str) -> String {
+    format!(
+        "SELECT * FROM (
+            SELECT messages.*,
+                snippet(messages_fts, 0, char(2), char(3), '…', 16) AS snippet,
+                messages_fts.rank AS rank
+            FROM messages_fts JOIN messages ON messages.id = messages_fts.rowid
+            WHERE messages_fts MATCH ? AND messages.wall = ?
+                AND (messages.expires_at IS NULL OR messages.expires_at > ?)
+                AND messages.deleted_at IS NULL AND messages.id {} ?
+            ORDER BY messages.id {}
+            LIMIT ?
+        ) ORDER BY rank, id DESC;",
+        cmp, order,
+    )
 }
 
 #[async_trait::async_trait]
 impl TDatabase for Sqlite {
-    async fn get_msgs(&self, count: GetMsgs, limit: u32) -> Result<Vec<Arc<Msg>>> {
-        let db = self.db.clone();
-        tracing::info!("get_msgs: {:?}, limit: {}", count, limit);
-        match count {
-            After(after) => {
-                if after == 0 {
-                    Ok(
-                        Messages::find()
-                            .order_by_desc(msg::Column::Id)
-                            .limit(limit as u64)
-                            .all(db.as_ref())
-                            .await?
-                            .iter()
-                            .map(|msg| msg.into())
-                            .map(Arc::new)
-                            .collect()
-                    )
-                } else {
-                    Ok(
-                        Messages::find()
-                            .filter(msg::Column::Id.gt(after as u32))
-                            .order_by_desc(msg::Column::Id)
-                            .limit(Some(limit as u64))
-                            .all(db.as_ref())
-                            .await?
-                            .iter()
-                            .map(|msg| msg.into())
-                            .map(Arc::new)
-                            .collect()
-                    )
-                }
-            },
-            Before(before) => Ok(
-                Messages::find()
-                    .filter(msg::Column::Id.lt(before as u32))
-                    .order_by_desc(msg::Column::Id)
-                    .limit(Some(limit as u64))
-                    .all(db.as_ref())
-                    .await?
-                    .iter()
-                    .map(|msg| msg.into())
-                    .map(Arc::new)
-                    .collect()
-            )
+    async fn get_msgs(&self, wall: &str, count: GetMsgs, filter: &MsgFilter, limit: u32) -> Result<Vec<Arc<Msg>>> {
+        orm::get_msgs(&self.db, wall, count, filter, limit).await
+    }
+
+    async fn send_msg(&self, msg: ReceiveMsg) -> Result<Arc<Msg>> {
+        orm::send_msg(&self.db, msg).await
+    }
+
+    async fn last_msg(&self, wall: &str) -> Result<u32> {
+        orm::last_msg(&self.db, wall).await
+    }
+
+    async fn delete_msg(&self, id: u32) -> Result<bool> {
+        orm::delete_msg(&self.db, id).await
+    }
+
+    async fn get_msg(&self, id: u32) -> Result<Option<Arc<Msg>>> {
+        orm::get_msg(&self.db, id).await
+    }
+
+    async fn delete_token_hash(&self, id: u32) -> Result<Option<Arc<str>>> {
+        orm::delete_token_hash(&self.db, id).await
+    }
+
+    async fn edit_msg(&self, id: u32, content: Arc<str>) -> Result<Option<Arc<Msg>>> {
+        orm::edit_msg(&self.db, id, content).await
+    }
+
+    async fn raw_msg(&self, id: u32) -> Result<Option<RawMsg>> {
+        orm::raw_msg(&self.db, id).await
+    }
+
+    async fn set_pinned(&self, id: u32, pinned: bool, until: Option<u64>) -> Result<Option<Arc<Msg>>> {
+        orm::set_pinned(&self.db, id, pinned, until).await
+    }
+
+    async fn pinned(&self, wall: &str) -> Result<Vec<Arc<Msg>>> {
+        orm::pinned(&self.db, wall).await
+    }
+
+    async fn set_banned(&self, ip: &str, banned: bool) -> Result<bool> {
+        orm::set_banned(&self.db, ip, banned).await
+    }
+
+    async fn is_banned(&self, ip: &str) -> Result<bool> {
+        orm::is_banned(&self.db, ip).await
+    }
+
+    async fn banned_ips(&self) -> Result<Vec<Ban>> {
+        orm::banned_ips(&self.db).await
+    }
+
+    async fn search(&self, wall: &str, query: &str, count: GetMsgs, limit: u32) -> Result<Vec<SearchHit>> {
+        // every word quoted, so nothing in the query is taken for fts5 syntax
+        let terms = search::words(query)
+            .map(|word| format!("\"{}\"", word))
+            .collect::<Vec<_>>()
+            .join(" ");
+        if terms.is_empty() {
+            return Ok(Vec::new());
         }
+        orm::search(&self.db, search_sql, terms, wall, count, limit).await
+    }
+
+    async fn thread(&self, id: u32) -> Result<Vec<Arc<Msg>>> {
+        orm::thread(&self.db, id).await
+    }
+
+    async fn react(&self, id: u32, emoji: &str, ip: &str, on: bool) -> Result<Option<Arc<Msg>>> {
+        orm::react(&self.db, id, emoji, ip, on).await
+    }
+
+    async fn export(&self, wall: Option<&str>, after: u32, limit: u32) -> Result<Vec<ExportedMsg>> {
+        orm::export(&self.db, wall, after, limit).await
+    }
+
+    async fn import(&self, msgs: Vec<ExportedMsg>) -> Result<()> {
+        orm::import(&self.db, msgs).await
+    }
+
+    async fn prunable(&self, wall: &str, sent_before: Option<u64>, keep: Option<u32>) -> Result<u32> {
+        orm::prunable(&self.db, wall, sent_before, keep).await
     }
 
-    async fn send_msg(&self, msg: ReceiveMsg) -> Result<()> {
-        let db = self.db.clone();
-        msg::ActiveModel {
-            author: Set(msg.author.to_string()),
-            content: Set(msg.content.to_string()),
-            timestamp: Set(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64),
-            ..Default::default() }
-            .insert(db.as_ref())
-            .await?;
-        Ok(())
-    }
-
-    async fn last_msg(&self) -> Result<u32> {
-        let db = self.db.clone();
-        Ok(
-            Messages::find()
-                .order_by_desc(msg::Column::Id)
-                .one(db.as_ref())
-                .await?
-                .map(|msg| msg.id)
-                .unwrap_or(
-                    0
-                )
-        )
-    }
-}
\ No newline at end of file
+    async fn prune(&self, wall: &str, up_to: u32) -> Result<u64> {
+        orm::prune(&self.db, wall, up_to).await
+    }
+
+    async fn remove_expired(&self, now: u64) -> Result<Vec<Arc<Msg>>> {
+        orm::remove_expired(&self.db, now).await
+    }
+
+    async fn pending_deliveries(&self) -> Result<Vec<Delivery>> {
+        orm::pending_deliveries(&self.db).await
+    }
+
+    async fn delivered(&self, id: u32, integration: &str) -> Result<()> {
+        orm::delivered(&self.db, id, integration).await
+    }
+}
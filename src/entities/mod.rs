@@ -1 +1,4 @@
-pub mod msg;
\ No newline at end of file
+pub mod msg;
+pub mod ban;
+pub mod reaction;
+pub mod delivery;
\ No newline at end of file
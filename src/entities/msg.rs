@@ -4,10 +4,19 @@ use sea_orm::entity::prelude::*;
 #[sea_orm(table_name = "messages")]
 pub struct Model {
     #[sea_orm(primary_key)]
-    pub id: u32,
+    pub id: i32,
     pub author: String,
     pub content: String,
     pub timestamp: i64,
+    pub deleted_at: Option<i64>,
+    pub edited_at: Option<i64>,
+    pub delete_token_hash: Option<String>,
+    pub client_ip: Option<String>,
+    pub pinned: bool,
+    pub reply_to: Option<i32>,
+    pub wall: String,
+    pub pinned_until: Option<i64>,
+    pub expires_at: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
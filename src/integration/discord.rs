@@ -0,0 +1,153 @@
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+use crate::database::Msg;
+use crate::integration::{self, Integration, RetryAfter};
+use crate::utils::markdown::escape_markdown;
+
+// what Discord takes as a webhook username
+const MAX_USERNAME_CHARS: usize = 80;
+
+/// Posts new messages to a channel through one of its Discord webhooks.
+pub struct Discord {
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl Discord {
+    pub fn new(url: String) -> Self {
+        let agent = integration::agent(integration::DEFAULT_TIMEOUT);
+        Self { url, agent }
+    }
+}
+
+/// The author as a webhook username. Discord refuses some characters and words
+/// in those, so the characters are replaced and the words lead to a stand-in.
+fn username(author: &str) -> String {
+    let name: String = author.chars()
+        .map(|c| if matches!(c, '@' | '#' | ':' | '`') { '_' } else { c })
+        .take(MAX_USERNAME_CHARS)
+        .collect();
+    let lower = name.to_lowercase();
+    if lower.contains("discord") || lower.contains("clyde") || lower == "everyone" || lower == "here" {
+        return "wall".to_string();
+    }
+    name
+}
+
+#[async_trait::async_trait]
+impl Integration for Discord {
+    fn name(&self) -> &str {
+        "discord"
+    }
+
+    async fn integrate(&self, msg: Arc<Msg>) -> Result<()> {
+        let body = json!({
+            "username": username(&msg.author),
+            "content": escape_markdown(&msg.content),
+            // no pings, whatever the message says
+            "allowed_mentions": {"parse": []},
+        });
+        tracing::info!("Sending message {} to Discord", msg.id);
+
+        let (agent, url) = (self.agent.clone(), self.url.clone());
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let mut response = agent.post(&url)
+                .config()
+                .http_status_as_error(false)
+                .build()
+                .send_json(body)?;
+            let status = response.status();
+            if status.is_success() {
+                return Ok(());
+            }
+            if status.as_u16() == 429 {
+                // seconds, fractions included
+                let retry_after = response.body_mut().read_json::<Value>().ok()
+                    .and_then(|answer| answer["retry_after"].as_f64())
+                    .unwrap_or(1.0);
+                return Err(RetryAfter(Duration::from_secs_f64(retry_after.max(0.0))).into());
+            }
+            bail!("Discord answered {}", status)
+        }).await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use axum::{Json, Router, routing::post};
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use tokio::sync::mpsc;
+    use super::*;
+
+    fn msg(author: &str, content: &str) -> Arc<Msg> {
+        Arc::new(Msg {
+            id: 7,
+            wall: Arc::from("main"),
+            author: Arc::from(author),
+            content: Arc::from(content),
+            timestamp: 100,
+            edited_at: None,
+            reply_to: None,
+            pinned: false,
+            pinned_until: None,
+            reactions: BTreeMap::new(),
+            deleted_at: None,
+            expires_at: None,
+        })
+    }
+
+    /// A local stand-in for Discord, answering with `status` and `answer`
+    /// and passing on the body of every request it gets.
+    async fn discord(status: StatusCode, answer: Value) -> (String, mpsc::UnboundedReceiver<Value>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let app = Router::new().route("/api/webhooks/1/token", post(move |Json(body): Json<Value>| async move {
+            tx.send(body).unwrap();
+            (status, Json(answer)).into_response()
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api/webhooks/1/token", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, rx)
+    }
+
+    #[test]
+    fn markdown_is_escaped() {
+        assert_eq!(escape_markdown("**bold** _it_ `code` > quote"), r"\*\*bold\*\* \_it\_ \`code\` \> quote");
+        assert_eq!(escape_markdown(r"a\b [link](x)"), r"a\\b \[link\]\(x\)");
+    }
+
+    #[test]
+    fn usernames_are_allowed_ones() {
+        assert_eq!(username("anna"), "anna");
+        assert_eq!(username("a@b#c:d"), "a_b_c_d");
+        assert_eq!(username("Discord Mod"), "wall");
+    }
+
+    #[tokio::test]
+    async fn posts_without_mentions() {
+        let (url, mut received) = discord(StatusCode::NO_CONTENT, Value::Null).await;
+        Discord::new(url).integrate(msg("anna", "hi @everyone *")).await.unwrap();
+        let body = received.recv().await.unwrap();
+        assert_eq!(body["username"], "anna");
+        assert_eq!(body["content"], r"hi @everyone \*");
+        assert_eq!(body["allowed_mentions"], json!({"parse": []}));
+    }
+
+    #[tokio::test]
+    async fn rate_limits_say_when_to_retry() {
+        let (url, _received) = discord(StatusCode::TOO_MANY_REQUESTS, json!({"retry_after": 1.5, "global": false})).await;
+        let e = Discord::new(url).integrate(msg("anna", "hi")).await.unwrap_err();
+        assert_eq!(e.downcast_ref::<RetryAfter>().unwrap().0, Duration::from_millis(1500));
+    }
+
+    #[tokio::test]
+    async fn refusals_are_errors() {
+        let (url, _received) = discord(StatusCode::BAD_REQUEST, json!({"message": "Invalid"})).await;
+        let e = Discord::new(url).integrate(msg("anna", "hi")).await.unwrap_err();
+        assert!(e.downcast_ref::<RetryAfter>().is_none());
+    }
+}
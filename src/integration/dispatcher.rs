@@ -0,0 +1,274 @@
+//! Runs integrations off the request path: a bounded queue drained by a few
+//! workers, every call timed out and retried with exponential backoff.
+//! Posts are also in the database's outbox until they succeed, so those the
+//! queue drops or a restart cuts short are picked up again on the next start.
+//! A message is only ever taken back once its post is done, not while that
+//! is still queued or running.
+
+use std::collections::HashMap;
+use std::sync::{self, Arc};
+use std::time::Duration;
+use anyhow::anyhow;
+use tokio::sync::{mpsc, Mutex};
+use tokio::sync::mpsc::error::TrySendError;
+use crate::database::{Database, Delivery, Msg};
+use crate::integration::{Integration, RetryAfter};
+
+// wait before the first retry, doubled for each one after it
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct DispatcherConfig {
+    /// calls running at the same time
+    pub workers: usize,
+    /// calls waiting for a worker, more are dropped
+    pub queue_capacity: usize,
+    /// tries per call, the first one included
+    pub max_attempts: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Action {
+    Integrate,
+    Retract,
+}
+
+impl Action {
+    /// What becomes of a call that doesn't go through.
+    fn fate(self) -> &'static str {
+        match self {
+            Self::Integrate => "it stays in the outbox until the next start",
+            Self::Retract => "it is lost",
+        }
+    }
+}
+
+struct Job {
+    integration: Arc<dyn Integration>,
+    action: Action,
+    msg: Arc<Msg>,
+}
+
+/// Posts queued or running, by message id and integration name, each with
+/// whether the message went meanwhile and has to be taken back after it.
+type InFlight = Arc<sync::Mutex<HashMap<(u32, String), bool>>>;
+
+/// Where integration calls are queued, cheap to clone.
+#[derive(Clone)]
+pub struct Dispatcher {
+    queue: mpsc::Sender<Job>,
+    in_flight: InFlight,
+}
+
+impl Dispatcher {
+    /// Spawns the workers, they stop once every clone of the dispatcher is gone.
+    /// Successful posts are marked delivered in `db`.
+    pub fn start(config: &DispatcherConfig, db: Arc<dyn Database>) -> Self {
+        let (queue, jobs) = mpsc::channel(config.queue_capacity);
+        let jobs = Arc::new(Mutex::new(jobs));
+        let in_flight = InFlight::default();
+        for _ in 0..config.workers {
+            tokio::spawn(work(jobs.clone(), db.clone(), config.max_attempts, in_flight.clone()));
+        }
+        Self { queue, in_flight }
+    }
+
+    /// Queues what was left in the outbox, waiting for room rather than dropping any.
+    /// `integrations` are those of each wall by its name.
+    pub fn redeliver(&self, pending: Vec<Delivery>, integrations: &HashMap<String, Arc<[Arc<dyn Integration>]>>) {
+        let mut jobs = Vec::new();
+        for Delivery { msg, integration } in pending {
+            let found = integrations.get(msg.wall.as_ref())
+                .and_then(|on_wall| on_wall.iter().find(|candidate| candidate.name() == &*integration));
+            match found {
+                Some(found) => jobs.push(Job { integration: found.clone(), action: Action::Integrate, msg }),
+                None => tracing::warn!(
+                    "Message {} waits for {}, which wall {} no longer has",
+                    msg.id, integration, msg.wall,
+                ),
+            }
+        }
+        if jobs.is_empty() {
+            return;
+        }
+        tracing::info!("Redelivering {} posts from the outbox", jobs.len());
+        let mut in_flight = self.in_flight.lock().unwrap();
+        for job in &jobs {
+            in_flight.insert((job.msg.id, job.integration.name().to_string()), false);
+        }
+        drop(in_flight);
+        let queue = self.queue.clone();
+        tokio::spawn(async move {
+            for job in jobs {
+                if queue.send(job).await.is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Queues posting a new message to each of `integrations`.
+    pub fn integrate(&self, integrations: &[Arc<dyn Integration>], msg: &Arc<Msg>) {
+        self.push_all(integrations, Action::Integrate, msg);
+    }
+
+    /// Queues taking a message that is gone back from each of `integrations`.
+    pub fn retract(&self, integrations: &[Arc<dyn Integration>], msg: &Arc<Msg>) {
+        self.push_all(integrations, Action::Retract, msg);
+    }
+
+    fn push_all(&self, integrations: &[Arc<dyn Integration>], action: Action, msg: &Arc<Msg>) {
+        for integration in integrations {
+            let key = (msg.id, integration.name().to_string());
+            match action {
+                // before it is queued, a worker may be done with it right away
+                Action::Integrate => {
+                    self.in_flight.lock().unwrap().insert(key.clone(), false);
+                },
+                // still being posted, the worker posting it takes it back after
+                Action::Retract => if let Some(retract) = self.in_flight.lock().unwrap().get_mut(&key) {
+                    *retract = true;
+                    continue;
+                },
+            }
+            let job = Job { integration: integration.clone(), action, msg: msg.clone() };
+            let Err(e) = self.queue.try_send(job) else {
+                continue;
+            };
+            if let Action::Integrate = action {
+                self.in_flight.lock().unwrap().remove(&key);
+            }
+            match e {
+                // never wait for room, that would hold up whoever sent the message
+                TrySendError::Full(job) => tracing::warn!(
+                    "Integration queue is full ({} waiting), dropping {:?} of message {} for {}, {}",
+                    self.queue.max_capacity(), job.action, job.msg.id, job.integration.name(), job.action.fate(),
+                ),
+                TrySendError::Closed(job) => tracing::error!(
+                    "Integration workers are gone, dropping {:?} of message {} for {}, {}",
+                    job.action, job.msg.id, job.integration.name(), job.action.fate(),
+                ),
+            }
+        }
+    }
+}
+
+async fn work(jobs: Arc<Mutex<mpsc::Receiver<Job>>>, db: Arc<dyn Database>, max_attempts: u32, in_flight: InFlight) {
+    loop {
+        // the lock is only held while waiting, so idle workers take turns
+        let Some(job) = jobs.lock().await.recv().await else {
+            return;
+        };
+        let (integration, action, msg) = (job.integration.clone(), job.action, job.msg.clone());
+        run(job, db.as_ref(), max_attempts).await;
+        if let Action::Integrate = action
+            && in_flight.lock().unwrap().remove(&(msg.id, integration.name().to_string())) == Some(true) {
+            run(Job { integration, action: Action::Retract, msg }, db.as_ref(), max_attempts).await;
+        }
+    }
+}
+
+async fn run(job: Job, db: &dyn Database, max_attempts: u32) {
+    let Job { integration, action, msg } = job;
+    let mut backoff = FIRST_BACKOFF;
+    for attempt in 1..=max_attempts {
+        let call = match action {
+            Action::Integrate => integration.integrate(msg.clone()),
+            Action::Retract => integration.retract(msg.clone()),
+        };
+        let e = match tokio::time::timeout(integration.timeout(), call).await {
+            Ok(Ok(())) => {
+                if let Action::Integrate = action
+                    && let Err(e) = db.delivered(msg.id, integration.name()).await {
+                    // posted again after the next start, better than never
+                    tracing::error!("Failed to mark message {} delivered to {}: {}", msg.id, integration.name(), e);
+                }
+                return;
+            },
+            Ok(Err(e)) => e,
+            Err(_) => anyhow!("timed out after {:?}", integration.timeout()),
+        };
+        if attempt == max_attempts {
+            tracing::error!(
+                "{} gave up on {:?} of message {} after {} attempts, {}: {}",
+                integration.name(), action, msg.id, attempt, action.fate(), e,
+            );
+            return;
+        }
+        let wait = e.downcast_ref::<RetryAfter>().map_or(backoff, |retry| retry.0);
+        tracing::warn!(
+            "{} failed {:?} of message {} (attempt {}/{}), retrying in {:?}: {}",
+            integration.name(), action, msg.id, attempt, max_attempts, wait, e,
+        );
+        tokio::time::sleep(wait).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use crate::database::mock::MockBase;
+    use super::*;
+
+    /// Takes its time posting and notes down every call it gets.
+    #[derive(Default)]
+    struct Slow {
+        calls: sync::Mutex<Vec<&'static str>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Integration for Slow {
+        fn name(&self) -> &str {
+            "slow"
+        }
+
+        async fn integrate(&self, _msg: Arc<Msg>) -> anyhow::Result<()> {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            self.calls.lock().unwrap().push("integrate");
+            Ok(())
+        }
+
+        async fn retract(&self, _msg: Arc<Msg>) -> anyhow::Result<()> {
+            self.calls.lock().unwrap().push("retract");
+            Ok(())
+        }
+    }
+
+    fn msg(id: u32) -> Arc<Msg> {
+        Arc::new(Msg {
+            id,
+            wall: Arc::from("main"),
+            author: Arc::from("author"),
+            content: Arc::from("soon gone"),
+            timestamp: 100,
+            edited_at: None,
+            reply_to: None,
+            pinned: false,
+            pinned_until: None,
+            reactions: BTreeMap::new(),
+            deleted_at: None,
+            expires_at: Some(101),
+        })
+    }
+
+    #[tokio::test]
+    async fn retracts_wait_for_their_post() {
+        let config = DispatcherConfig { workers: 2, queue_capacity: 8, max_attempts: 1 };
+        let dispatcher = Dispatcher::start(&config, Arc::new(MockBase::new()));
+        let slow = Arc::new(Slow::default());
+        let integrations: [Arc<dyn Integration>; 1] = [slow.clone()];
+
+        // the second worker would take the retract while the first still posts
+        dispatcher.integrate(&integrations, &msg(1));
+        dispatcher.retract(&integrations, &msg(1));
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(*slow.calls.lock().unwrap(), ["integrate", "retract"]);
+
+        // once posted, it is taken back right away
+        dispatcher.retract(&integrations, &msg(1));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(*slow.calls.lock().unwrap(), ["integrate", "retract", "retract"]);
+    }
+}
@@ -0,0 +1,157 @@
+use std::sync::Arc;
+use anyhow::Result;
+use serde_json::{json, Value};
+use crate::database::Msg;
+use crate::integration::{self, Integration};
+use crate::utils::html::escape_html;
+
+/// Mirrors new messages into a Matrix room through the client-server API.
+pub struct Matrix {
+    homeserver: String,
+    access_token: String,
+    room_id: String,
+    agent: ureq::Agent,
+}
+
+impl Matrix {
+    /// `homeserver` is its base URL, e.g. `https://matrix.org`.
+    pub fn new(homeserver: String, access_token: String, room_id: String) -> Self {
+        let agent = integration::agent(integration::DEFAULT_TIMEOUT);
+        let homeserver = homeserver.trim_end_matches('/').to_string();
+        Self { homeserver, access_token, room_id, agent }
+    }
+}
+
+/// Percent-encodes all but unreserved characters, room ids (`!abc:example.org`) go into paths.
+fn encode_segment(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// The `m.room.message` event for a message, plain and HTML.
+fn event(msg: &Msg) -> Value {
+    json!({
+        "msgtype": "m.text",
+        "body": format!("{}: {}", msg.author, msg.content),
+        "format": "org.matrix.custom.html",
+        "formatted_body": format!("<b>{}</b>: {}", escape_html(&msg.author), escape_html(&msg.content)),
+    })
+}
+
+#[async_trait::async_trait]
+impl Integration for Matrix {
+    fn name(&self) -> &str {
+        "matrix"
+    }
+
+    async fn integrate(&self, msg: Arc<Msg>) -> Result<()> {
+        // the same for every retry, the homeserver sends each transaction only once
+        let txn_id = format!("wall-msg-{}", integration::msg_key(&msg));
+        let url = format!(
+            "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+            self.homeserver, encode_segment(&self.room_id), txn_id,
+        );
+        tracing::info!("Sending message {} to Matrix (room: {})", msg.id, self.room_id);
+
+        let agent = self.agent.clone();
+        let authorization = format!("Bearer {}", self.access_token);
+        let body = event(&msg);
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            agent.put(&url)
+                .header("Authorization", &authorization)
+                .send_json(body)?;
+            Ok(())
+        }).await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use axum::{Json, Router};
+    use axum::extract::Path;
+    use axum::http::HeaderMap;
+    use axum::routing::put;
+    use tokio::sync::mpsc;
+    use super::*;
+
+    fn msg() -> Arc<Msg> {
+        Arc::new(Msg {
+            id: 7,
+            wall: Arc::from("main"),
+            author: Arc::from("anna"),
+            content: Arc::from("<b>hi</b> & bye"),
+            timestamp: 100,
+            edited_at: None,
+            reply_to: None,
+            pinned: false,
+            pinned_until: None,
+            reactions: BTreeMap::new(),
+            deleted_at: None,
+            expires_at: None,
+        })
+    }
+
+    /// A local stand-in for a homeserver, passing on the room, transaction id,
+    /// authorization and body of every event sent to it.
+    async fn homeserver() -> (String, mpsc::UnboundedReceiver<(String, String, String, Value)>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/_matrix/client/v3/rooms/{room}/send/m.room.message/{txn}",
+            put(move |Path((room, txn)): Path<(String, String)>, headers: HeaderMap, Json(body): Json<Value>| async move {
+                let authorization = headers["authorization"].to_str().unwrap().to_string();
+                tx.send((room, txn.clone(), authorization, body)).unwrap();
+                Json(json!({"event_id": format!("$event-{}", txn)}))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, rx)
+    }
+
+    #[tokio::test]
+    async fn sends_room_messages() {
+        let (url, mut received) = homeserver().await;
+        let matrix = Matrix::new(url, "syt_token".to_string(), "!room:example.org".to_string());
+        matrix.integrate(msg()).await.unwrap();
+
+        let (room, txn, authorization, body) = received.recv().await.unwrap();
+        assert_eq!(room, "!room:example.org");
+        assert_eq!(txn, "wall-msg-main-7-100");
+        assert_eq!(authorization, "Bearer syt_token");
+        assert_eq!(body["msgtype"], "m.text");
+        assert_eq!(body["body"], "anna: <b>hi</b> & bye");
+        assert_eq!(body["formatted_body"], "<b>anna</b>: &lt;b&gt;hi&lt;/b&gt; &amp; bye");
+    }
+
+    #[tokio::test]
+    async fn retries_reuse_the_transaction() {
+        let (url, mut received) = homeserver().await;
+        let matrix = Matrix::new(url, "syt_token".to_string(), "!room:example.org".to_string());
+        matrix.integrate(msg()).await.unwrap();
+        matrix.integrate(msg()).await.unwrap();
+        assert_eq!(received.recv().await.unwrap().1, received.recv().await.unwrap().1);
+    }
+
+    #[tokio::test]
+    async fn restarts_reuse_the_transaction() {
+        // what a redelivery after a restart runs into, another instance with the same message
+        let (url, mut received) = homeserver().await;
+        Matrix::new(url.clone(), "syt_token".to_string(), "!room:example.org".to_string()).integrate(msg()).await.unwrap();
+        Matrix::new(url, "syt_token".to_string(), "!room:example.org".to_string()).integrate(msg()).await.unwrap();
+        assert_eq!(received.recv().await.unwrap().1, received.recv().await.unwrap().1);
+    }
+
+    #[test]
+    fn room_ids_are_encoded() {
+        assert_eq!(encode_segment("!room:example.org"), "%21room%3Aexample.org");
+    }
+}
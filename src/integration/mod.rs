@@ -1,9 +1,76 @@
 pub mod telegram;
+pub mod webhook;
+pub mod discord;
+pub mod matrix;
+pub mod dispatcher;
 
-use crate::database::ReceiveMsg;
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::Result;
+use serde_json::Value;
+use crate::database::Msg;
 
+/// How long a call may take unless the integration says otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Somewhere else messages get posted to. Calls go through the `Dispatcher`,
+/// which times them out and retries them when they fail.
+#[async_trait::async_trait]
 pub trait Integration: Send + Sync {
-    fn integrate(&self, msg: ReceiveMsg) -> Box<dyn FnOnce() + Send + 'static>;
+    /// how it shows up in logs and the outbox, unique on its wall
+    fn name(&self) -> &str;
+    /// How long one call may take before it counts as failed.
+    fn timeout(&self) -> Duration {
+        DEFAULT_TIMEOUT
+    }
+    async fn integrate(&self, msg: Arc<Msg>) -> Result<()>;
+    /// Takes back what `integrate` posted for a message that is gone,
+    /// integrations that can't do nothing.
+    async fn retract(&self, _msg: Arc<Msg>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A failed call that says when to try again, the dispatcher waits that long
+/// instead of its own backoff.
+#[derive(Debug)]
+pub struct RetryAfter(pub Duration);
+
+impl std::fmt::Display for RetryAfter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rate limited, try again in {:?}", self.0)
+    }
+}
+
+impl std::error::Error for RetryAfter {}
+
+/// Tells a message apart from any other, made of what is stored with it so it stays
+/// the same on every retry, redeliveries after a restart included. The id alone doesn't
+/// do, `memory://` hands the same ones out again after a restart and imports bring in old ones.
+pub fn msg_key(msg: &Msg) -> String {
+    format!("{}-{}-{}", msg.wall, msg.id, msg.timestamp)
+}
+
+/// Blocking HTTP client for integrations, giving up on calls after `timeout`.
+pub fn agent(timeout: Duration) -> ureq::Agent {
+    ureq::Agent::new_with_config(
+        ureq::Agent::config_builder()
+            .timeout_global(Some(timeout))
+            .build()
+    )
+}
+
+/// POSTs `body` as JSON on a blocking thread and reads back the JSON answer,
+/// anything but a 2xx status is an error.
+pub async fn post_json(agent: &ureq::Agent, url: String, body: Value) -> Result<Value> {
+    let agent = agent.clone();
+    tokio::task::spawn_blocking(move || -> Result<Value> {
+        Ok(agent.post(&url).send_json(body)?.body_mut().read_json()?)
+    }).await?
 }
 
 pub use telegram::Telegram;
+pub use webhook::Webhook;
+pub use discord::Discord;
+pub use matrix::Matrix;
+pub use dispatcher::{Dispatcher, DispatcherConfig};
@@ -1,40 +1,66 @@
-use crate::database::ReceiveMsg;
-use crate::integration::Integration;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use anyhow::Result;
+use crate::database::Msg;
+use crate::integration::{self, Integration};
 use crate::utils::html::escape_html;
-use serde_json::json;
+use serde_json::{json, Value};
 
 pub struct Telegram {
     token: String,
     chat_id: String,
+    agent: ureq::Agent,
+    /// Telegram's ids of posted ephemeral messages, by wall id, to delete them once they expire.
+    /// Only kept in memory, what was posted before a restart stays.
+    posted: Mutex<HashMap<u32, i64>>,
 }
 
 impl Telegram {
     pub fn new(token: String, chat_id: String) -> Self {
-        Self { token, chat_id }
+        let agent = integration::agent(integration::DEFAULT_TIMEOUT);
+        Self { token, chat_id, agent, posted: Mutex::default() }
+    }
+
+    async fn call(&self, method: &str, body: Value) -> Result<Value> {
+        let url = format!("https://api.telegram.org/bot{}/{}", self.token, method);
+        integration::post_json(&self.agent, url, body).await
     }
 }
 
+#[async_trait::async_trait]
 impl Integration for Telegram {
-    fn integrate(&self, msg: ReceiveMsg) -> Box<dyn FnOnce() + Send + 'static> {
+    fn name(&self) -> &str {
+        "telegram"
+    }
+
+    async fn integrate(&self, msg: Arc<Msg>) -> Result<()> {
         let author = escape_html(msg.author.as_ref());
         let content = escape_html(msg.content.as_ref());
-
-        let url = format!(
-            "https://api.telegram.org/bot{}/sendMessage",
-            self.token
-        );
-        let chat_id = self.chat_id.clone();
         let text = format!("<b>{}</b>:\n{}", author, content);
         tracing::info!("Sending a message to Telegram (chat: {}): {}", self.chat_id, text);
-        Box::new(move || match ureq::post(&url)
-            .content_type("application/json")
-            .send_json(&json!({
-                "chat_id": chat_id,
-                "text": text,
-                "parse_mode": "HTML",
-            })) {
-            Ok(_) => {}
-            Err(e) => tracing::error!("Failed to send a message to Telegram: {}", e),
-        })
+        let answer = self.call("sendMessage", json!({
+            "chat_id": self.chat_id,
+            "text": text,
+            "parse_mode": "HTML",
+        })).await?;
+        if msg.expires_at.is_some()
+            && let Some(message_id) = answer["result"]["message_id"].as_i64() {
+            self.posted.lock().unwrap().insert(msg.id, message_id);
+        }
+        Ok(())
+    }
+
+    async fn retract(&self, msg: Arc<Msg>) -> Result<()> {
+        let Some(message_id) = self.posted.lock().unwrap().get(&msg.id).copied() else {
+            return Ok(());
+        };
+        tracing::info!("Deleting message {} from Telegram (chat: {})", msg.id, self.chat_id);
+        self.call("deleteMessage", json!({
+            "chat_id": self.chat_id,
+            "message_id": message_id,
+        })).await?;
+        // only once it's gone, a retry has to find it still
+        self.posted.lock().unwrap().remove(&msg.id);
+        Ok(())
     }
 }
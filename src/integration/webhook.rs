@@ -0,0 +1,156 @@
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use crate::database::Msg;
+use crate::integration::{self, Integration};
+
+pub const SIGNATURE_HEADER: &str = "X-Wall-Signature";
+pub const DELIVERY_HEADER: &str = "X-Wall-Delivery";
+pub const TIMESTAMP_HEADER: &str = "X-Wall-Timestamp";
+
+/// What gets POSTed, the signature covers all of it.
+#[derive(Serialize)]
+struct Payload<'a> {
+    /// the same on every retry, redeliveries after a restart included,
+    /// so receivers can skip ones they have seen
+    delivery: &'a str,
+    /// when this attempt was made, receivers should refuse old ones
+    timestamp: u64,
+    msg: &'a Msg,
+}
+
+/// `sha256=<hex>` of the HMAC-SHA256 of `body` under `secret`.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// POSTs new messages as signed JSON to a URL of the wall's choosing.
+pub struct Webhook {
+    name: String,
+    url: String,
+    secret: String,
+    agent: ureq::Agent,
+}
+
+impl Webhook {
+    pub fn new(url: String, secret: String) -> Self {
+        let agent = integration::agent(integration::DEFAULT_TIMEOUT);
+        Self { name: format!("webhook {}", url), url, secret, agent }
+    }
+}
+
+#[async_trait::async_trait]
+impl Integration for Webhook {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn integrate(&self, msg: Arc<Msg>) -> Result<()> {
+        let delivery = format!("msg-{}", integration::msg_key(&msg));
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let body = serde_json::to_string(&Payload { delivery: &delivery, timestamp, msg: &msg })?;
+        let signature = sign(&self.secret, body.as_bytes());
+        tracing::info!("Posting message {} to {}", msg.id, self.url);
+
+        let (agent, url) = (self.agent.clone(), self.url.clone());
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            agent.post(&url)
+                .header(SIGNATURE_HEADER, &signature)
+                .header(DELIVERY_HEADER, &delivery)
+                .header(TIMESTAMP_HEADER, &timestamp.to_string())
+                .content_type("application/json")
+                .send(&body)?;
+            Ok(())
+        }).await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use axum::{Router, routing::post};
+    use axum::http::{HeaderMap, StatusCode};
+    use tokio::sync::mpsc;
+    use super::*;
+
+    fn msg() -> Arc<Msg> {
+        Arc::new(Msg {
+            id: 7,
+            wall: Arc::from("main"),
+            author: Arc::from("author"),
+            content: Arc::from("hello"),
+            timestamp: 100,
+            edited_at: None,
+            reply_to: None,
+            pinned: false,
+            pinned_until: None,
+            reactions: BTreeMap::new(),
+            deleted_at: None,
+            expires_at: None,
+        })
+    }
+
+    /// A local stand-in for the receiving end, answering with `status`
+    /// and passing on the headers and body of every request it gets.
+    async fn receiver(status: StatusCode) -> (String, mpsc::UnboundedReceiver<(HeaderMap, String)>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let app = Router::new().route("/hook", post(move |headers: HeaderMap, body: String| async move {
+            tx.send((headers, body)).unwrap();
+            status
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, rx)
+    }
+
+    #[test]
+    fn signature_matches_known_vector() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+        );
+    }
+
+    #[tokio::test]
+    async fn posts_signed_msgs() {
+        let (url, mut received) = receiver(StatusCode::OK).await;
+        let webhook = Webhook::new(url, "sekrit".to_string());
+        webhook.integrate(msg()).await.unwrap();
+
+        let (headers, body) = received.recv().await.unwrap();
+        let header = |name| headers.get(name).unwrap().to_str().unwrap().to_string();
+        assert_eq!(header(SIGNATURE_HEADER), sign("sekrit", body.as_bytes()));
+        assert_ne!(header(SIGNATURE_HEADER), sign("other", body.as_bytes()));
+        assert_eq!(header(DELIVERY_HEADER), "msg-main-7-100");
+        assert_eq!(header("content-type"), "application/json");
+
+        let payload: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(payload["delivery"], "msg-main-7-100");
+        assert_eq!(payload["timestamp"].to_string(), header(TIMESTAMP_HEADER));
+        assert_eq!(payload["msg"]["content"], "hello");
+    }
+
+    #[tokio::test]
+    async fn restarts_reuse_the_delivery() {
+        // what a redelivery after a restart runs into, another instance with the same message
+        let (url, mut received) = receiver(StatusCode::OK).await;
+        Webhook::new(url.clone(), "sekrit".to_string()).integrate(msg()).await.unwrap();
+        Webhook::new(url, "sekrit".to_string()).integrate(msg()).await.unwrap();
+        let delivery = |(headers, _): (HeaderMap, String)| headers[DELIVERY_HEADER].to_str().unwrap().to_string();
+        assert_eq!(delivery(received.recv().await.unwrap()), delivery(received.recv().await.unwrap()));
+    }
+
+    #[tokio::test]
+    async fn refusals_are_errors() {
+        let (url, _received) = receiver(StatusCode::INTERNAL_SERVER_ERROR).await;
+        let webhook = Webhook::new(url, "sekrit".to_string());
+        assert!(webhook.integrate(msg()).await.is_err());
+    }
+}
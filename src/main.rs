@@ -1,12 +1,14 @@
 mod args;
 mod database;
 mod routers;
-#[cfg(feature = "sqlite_db")]
+#[cfg(any(feature = "sqlite_db", feature = "postgres_db"))]
 mod entities;
 mod integration;
+mod sweeper;
 mod utils;
 
 use axum::Router;
+use std::collections::HashMap;
 use std::sync::Arc;
 
 
@@ -18,28 +20,81 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!("Args: {:#?}", args);
     tracing::info!("Current dir: {}", std::env::current_dir()?.display());
 
-    #[cfg(not(feature = "sqlite_db"))]
-    let db = database::mock::MockBase::new();
+    if let args::Command::Migrate = args.command {
+        return database::migrate(&args.database_url, &args.db_pool).await;
+    }
 
-    #[cfg(feature = "sqlite_db")]
-    let db =
-        database::sqlite::Sqlite::new(args.filename)
-            .await?;
+    let db = database::connect(&args.database_url, &args.db_pool).await?;
 
-    let telegram = Arc::new(integration::Telegram::new(
-        args.tg_token,
-        args.tg_chat_id,
-    ));
-    
-    let integrations: Arc<[Arc<dyn integration::Integration>]> = 
-        Arc::new([telegram]);
+    match &args.command {
+        args::Command::Export { path, wall, format } => {
+            let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+            let count = database::dump::export(db.as_ref(), wall.as_deref(), *format, file).await?;
+            tracing::info!("Exported {} messages to {}", count, path);
+            return Ok(());
+        },
+        args::Command::Import { path, format } => {
+            let msgs = database::dump::read(*format, std::fs::File::open(path)?)?;
+            let count = msgs.len();
+            db.import(msgs).await?;
+            tracing::info!("Imported {} messages from {}", count, path);
+            return Ok(());
+        },
+        args::Command::Serve | args::Command::Migrate => {},
+    }
 
-    let app =
+    let wall_names: Vec<_> = args.walls.iter().map(|wall| wall.name.as_str()).collect();
+    let updates = routers::updates::Updates::new(wall_names.iter().copied());
+    let mut app =
         Router::new()
-            .merge(routers::static_files::static_paths())
-            .merge(routers::msgs::msgs(db, integrations))
+            .merge(routers::static_files::static_paths(&wall_names))
             .merge(routers::git_info::git_info(args.repo_url));
 
+    let dispatcher = integration::Dispatcher::start(&args.dispatcher, db.clone());
+    let mut wall_integrations = HashMap::new();
+    for wall in &args.walls {
+        let mut integrations: Vec<Arc<dyn integration::Integration>> = Vec::new();
+        if let Some(chat_id) = &wall.tg_chat_id {
+            integrations.push(Arc::new(integration::Telegram::new(args.tg_token.clone(), chat_id.clone())));
+        }
+        if let Some(url) = &wall.discord_webhook {
+            integrations.push(Arc::new(integration::Discord::new(url.clone())));
+        }
+        if let (Some(room_id), Some(matrix)) = (&wall.matrix_room_id, &args.matrix) {
+            integrations.push(Arc::new(integration::Matrix::new(
+                matrix.homeserver.clone(), matrix.access_token.clone(), room_id.clone(),
+            )));
+        }
+        for webhook in &wall.webhooks {
+            integrations.push(Arc::new(integration::Webhook::new(webhook.url.clone(), webhook.secret.clone())));
+        }
+        let integrations: Arc<[_]> = integrations.into();
+        wall_integrations.insert(wall.name.clone(), integrations.clone());
+        let channel = updates.wall(&wall.name).expect("every wall has its channel");
+        let msgs = routers::msgs::msgs(db.clone(), wall, integrations, dispatcher.clone(), channel);
+        // the default wall keeps its old unprefixed routes too
+        if wall.name == database::DEFAULT_WALL {
+            app = app.merge(msgs.clone());
+        }
+        app = app.nest(&format!("/w/{}", wall.name), msgs);
+        tracing::info!("Serving wall {}", wall.name);
+    }
+
+    // before serving, so nothing sent from now on gets queued twice
+    dispatcher.redeliver(db.pending_deliveries().await?, &wall_integrations);
+    tokio::spawn(sweeper::run(db.clone(), wall_integrations, dispatcher, updates.clone(), args.sweep_interval_secs));
+
+    if let Some(retention) = args.retention {
+        let walls = wall_names.iter().map(|wall| wall.to_string()).collect();
+        tokio::spawn(database::retention::run(db.clone(), walls, retention));
+    }
+
+    if let Some(admin_token) = &args.admin_token {
+        let admin = routers::admin::admin(db, updates, admin_token, &args.admin_audit_log)?;
+        app = app.nest("/admin", admin);
+        tracing::info!("Admin API enabled, auditing to {}", args.admin_audit_log);
+    }
+
     let listener  = tokio::net::TcpListener::bind(("0.0.0.0", args.port)).await?;
     tracing::info!("Listening on {}", listener.local_addr()?);
     axum::serve(
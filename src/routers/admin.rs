@@ -0,0 +1,388 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use axum::extract::{ConnectInfo, DefaultBodyLimit, Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use crate::database::{Database, IdTaken};
+use crate::database::dump::{self, Format};
+use crate::routers::msgs::get_client_ip;
+use crate::routers::updates::{Update, Updates};
+use crate::utils::token;
+
+#[derive(Deserialize)]
+struct DeleteMsg {
+    id: u32,
+}
+
+#[derive(Deserialize)]
+struct BanIp {
+    ip: Arc<str>,
+}
+
+#[derive(Deserialize)]
+struct PinMsg {
+    id: u32,
+    pinned: bool,
+    /// unix time the pin runs out at, pinned for good without it
+    #[serde(default)]
+    until: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct Dump {
+    /// `jsonl` unless given
+    format: Option<String>,
+    /// exports every wall without it
+    wall: Option<String>,
+}
+
+impl Dump {
+    fn format(&self) -> anyhow::Result<Format> {
+        self.format.as_deref().map_or(Ok(Format::Jsonl), Format::parse)
+    }
+}
+
+// how many of the latest audit entries /admin/audit shows
+const AUDIT_LIMIT: usize = 100;
+// biggest dump /admin/import takes, larger walls go through the import command
+const IMPORT_LIMIT: usize = 64 * 1024 * 1024;
+
+/// One line of the audit log.
+#[derive(Serialize, Deserialize)]
+struct AuditEntry {
+    at: u64,
+    admin_ip: String,
+    action: String,
+    target: String,
+    ok: bool,
+}
+
+/// Append-only JSON Lines record of everything done through the admin API.
+struct AuditLog {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    fn open(path: &str) -> anyhow::Result<Self> {
+        let path = std::env::current_dir()?.join(path);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self { path, file: Mutex::new(file) })
+    }
+
+    async fn record(&self, admin_ip: &str, action: &str, target: impl ToString, ok: bool) {
+        let entry = AuditEntry {
+            at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            admin_ip: admin_ip.to_string(),
+            action: action.to_string(),
+            target: target.to_string(),
+            ok,
+        };
+        tracing::info!("admin {} {} by {}, ok: {}", entry.action, entry.target, entry.admin_ip, ok);
+
+        let mut file = self.file.lock().await;
+        let written = serde_json::to_string(&entry)
+            .map_err(anyhow::Error::from)
+            .and_then(|mut line| {
+                line.push('\n');
+                file.write_all(line.as_bytes())?;
+                Ok(file.sync_data()?)
+            });
+        if let Err(e) = written {
+            tracing::error!("Failed to write audit log: {}", e);
+        }
+    }
+
+    /// The latest `limit` entries, newest first.
+    async fn latest(&self, limit: usize) -> anyhow::Result<Vec<AuditEntry>> {
+        // holding the lock keeps half-written lines out
+        let _file = self.file.lock().await;
+        let mut entries = Vec::new();
+        for line in BufReader::new(File::open(&self.path)?).lines() {
+            entries.push(serde_json::from_str(&line?)?);
+        }
+        Ok(entries.into_iter().rev().take(limit).collect())
+    }
+}
+
+struct AdminState {
+    db: Arc<dyn Database>,
+    updates: Updates,
+    token_hash: String,
+    audit: AuditLog,
+}
+
+impl AdminState {
+    fn publish(&self, update: Update) {
+        self.updates.send(update);
+    }
+}
+
+/// Whether the request carries `Authorization: Bearer <admin token>`.
+fn is_admin(state: &AdminState, headers: &HeaderMap) -> bool {
+    let presented = headers.get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    // hashes are compared, so the time taken says nothing about the token
+    presented.is_some_and(|presented| token::hash(presented.trim()) == state.token_hash)
+}
+
+fn unauthorized(admin_ip: &str) -> Response {
+    tracing::warn!("Unauthorized admin request from IP: {}", admin_ip);
+    (StatusCode::UNAUTHORIZED,
+           serde_json::json!({"err": "Unauthorized"}).to_string())
+        .into_response()
+}
+
+fn internal_error(e: anyhow::Error) -> Response {
+    (StatusCode::INTERNAL_SERVER_ERROR,
+           serde_json::json!({"err": e.to_string()}).to_string())
+        .into_response()
+}
+
+fn not_found(what: &str) -> Response {
+    (StatusCode::NOT_FOUND,
+           serde_json::json!({"err": format!("No such {}", what)}).to_string())
+        .into_response()
+}
+
+fn ok() -> Response {
+    (StatusCode::OK,
+           serde_json::json!({"msg": "ok"}).to_string())
+        .into_response()
+}
+
+async fn delete_msg(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
+    Json(DeleteMsg { id }): Json<DeleteMsg>
+) -> Response {
+    let admin_ip = get_client_ip(&headers, Some(&ConnectInfo(addr)));
+    if !is_admin(&state, &headers) {
+        return unauthorized(&admin_ip);
+    }
+
+    let deleted = state.db.delete_msg(id).await;
+    state.audit.record(&admin_ip, "delete_msg", id, matches!(deleted, Ok(true))).await;
+    match deleted {
+        Ok(true) => {
+            // the tombstone still knows which wall to tell
+            if let Ok(Some(raw)) = state.db.raw_msg(id).await {
+                state.publish(Update::Delete { id, wall: raw.msg.wall.clone() });
+            }
+            ok()
+        },
+        Ok(false) => not_found("message"),
+        Err(e) => internal_error(e),
+    }
+}
+
+async fn set_banned(state: &AdminState, headers: &HeaderMap, admin_ip: &str, ip: &str, banned: bool) -> Response {
+    if !is_admin(state, headers) {
+        return unauthorized(admin_ip);
+    }
+
+    let changed = state.db.set_banned(ip, banned).await;
+    let action = if banned { "ban" } else { "unban" };
+    state.audit.record(admin_ip, action, ip, matches!(changed, Ok(true))).await;
+    match changed {
+        Ok(true) => ok(),
+        Ok(false) if banned => (StatusCode::CONFLICT,
+                   serde_json::json!({"err": "Already banned"}).to_string())
+            .into_response(),
+        Ok(false) => not_found("ban"),
+        Err(e) => internal_error(e),
+    }
+}
+
+async fn ban(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
+    Json(BanIp { ip }): Json<BanIp>
+) -> Response {
+    let admin_ip = get_client_ip(&headers, Some(&ConnectInfo(addr)));
+    set_banned(&state, &headers, &admin_ip, &ip, true).await
+}
+
+async fn unban(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
+    Json(BanIp { ip }): Json<BanIp>
+) -> Response {
+    let admin_ip = get_client_ip(&headers, Some(&ConnectInfo(addr)));
+    set_banned(&state, &headers, &admin_ip, &ip, false).await
+}
+
+async fn bans(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
+) -> Response {
+    let admin_ip = get_client_ip(&headers, Some(&ConnectInfo(addr)));
+    if !is_admin(&state, &headers) {
+        return unauthorized(&admin_ip);
+    }
+
+    match state.db.banned_ips().await {
+        Ok(bans) => (StatusCode::OK, Json(bans)).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+async fn pin(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
+    Json(PinMsg { id, pinned, until }): Json<PinMsg>
+) -> Response {
+    let admin_ip = get_client_ip(&headers, Some(&ConnectInfo(addr)));
+    if !is_admin(&state, &headers) {
+        return unauthorized(&admin_ip);
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    if pinned && until.is_some_and(|until| until <= now) {
+        return (StatusCode::BAD_REQUEST,
+                serde_json::json!({"err": "Pin would have run out already"}).to_string()).into_response();
+    }
+
+    let changed = state.db.set_pinned(id, pinned, until).await;
+    let action = if pinned { "pin" } else { "unpin" };
+    state.audit.record(&admin_ip, action, id, matches!(changed, Ok(Some(_)))).await;
+    match changed {
+        Ok(Some(msg)) => {
+            state.publish(Update::Edit(msg));
+            ok()
+        },
+        Ok(None) => not_found("message"),
+        Err(e) => internal_error(e),
+    }
+}
+
+async fn raw_msg(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
+    Path(id): Path<u32>,
+) -> Response {
+    let admin_ip = get_client_ip(&headers, Some(&ConnectInfo(addr)));
+    if !is_admin(&state, &headers) {
+        return unauthorized(&admin_ip);
+    }
+
+    // looking up who posted what is worth an entry too
+    let raw = state.db.raw_msg(id).await;
+    state.audit.record(&admin_ip, "view_msg", id, matches!(raw, Ok(Some(_)))).await;
+    match raw {
+        Ok(Some(raw)) => (StatusCode::OK, Json(raw)).into_response(),
+        Ok(None) => not_found("message"),
+        Err(e) => internal_error(e),
+    }
+}
+
+async fn audit(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
+) -> Response {
+    let admin_ip = get_client_ip(&headers, Some(&ConnectInfo(addr)));
+    if !is_admin(&state, &headers) {
+        return unauthorized(&admin_ip);
+    }
+
+    match state.audit.latest(AUDIT_LIMIT).await {
+        Ok(entries) => (StatusCode::OK, Json(entries)).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+async fn export(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
+    Query(query): Query<Dump>,
+) -> Response {
+    let admin_ip = get_client_ip(&headers, Some(&ConnectInfo(addr)));
+    if !is_admin(&state, &headers) {
+        return unauthorized(&admin_ip);
+    }
+    let format = match query.format() {
+        Ok(format) => format,
+        Err(e) => return (StatusCode::BAD_REQUEST,
+                serde_json::json!({"err": e.to_string()}).to_string()).into_response(),
+    };
+
+    let mut body = Vec::new();
+    let exported = dump::export(state.db.as_ref(), query.wall.as_deref(), format, &mut body).await;
+    state.audit.record(&admin_ip, "export", query.wall.as_deref().unwrap_or("*"), exported.is_ok()).await;
+    match exported {
+        Ok(_) => (StatusCode::OK, [(header::CONTENT_TYPE, format.content_type())], body).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+async fn import(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
+    Query(query): Query<Dump>,
+    body: String,
+) -> Response {
+    let admin_ip = get_client_ip(&headers, Some(&ConnectInfo(addr)));
+    if !is_admin(&state, &headers) {
+        return unauthorized(&admin_ip);
+    }
+    let msgs = match query.format().and_then(|format| dump::read(format, body.as_bytes())) {
+        Ok(msgs) => msgs,
+        Err(e) => return (StatusCode::BAD_REQUEST,
+                serde_json::json!({"err": e.to_string()}).to_string()).into_response(),
+    };
+
+    let count = msgs.len();
+    let imported = state.db.import(msgs).await;
+    state.audit.record(&admin_ip, "import", format!("{} messages", count), imported.is_ok()).await;
+    match imported {
+        Ok(()) => (StatusCode::OK,
+                   serde_json::json!({"msg": "ok", "imported": count}).to_string())
+            .into_response(),
+        Err(e) if e.is::<IdTaken>() => (StatusCode::CONFLICT,
+                   serde_json::json!({"err": e.to_string()}).to_string())
+            .into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// Moderation endpoints, to be nested under `/admin`.
+pub fn admin(
+    db: Arc<dyn Database>,
+    updates: Updates,
+    admin_token: &str,
+    audit_log: &str,
+) -> anyhow::Result<Router> {
+    let state = AdminState {
+        db,
+        updates,
+        token_hash: token::hash(admin_token),
+        audit: AuditLog::open(audit_log)?,
+    };
+    Ok(Router::new()
+        .route("/delete_msg", post(delete_msg))
+        .route("/ban", post(ban))
+        .route("/unban", post(unban))
+        .route("/bans", get(bans))
+        .route("/pin", post(pin))
+        .route("/msg/{id}", get(raw_msg))
+        .route("/audit", get(audit))
+        .route("/export", get(export))
+        .route("/import", post(import).layer(DefaultBodyLimit::max(IMPORT_LIMIT)))
+        .with_state(Arc::new(state)))
+}
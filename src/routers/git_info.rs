@@ -39,6 +39,7 @@ impl GitService {
         }
     }
 
+    #[allow(clippy::len_zero)]
     async fn get_info(&self) -> GitInfo {
         let commit_hash = Command::new("git")
             .args(["rev-parse", "HEAD"])
@@ -1,3 +1,6 @@
 pub mod static_files;
 pub mod msgs;
-pub mod git_info;
\ No newline at end of file
+pub mod git_info;
+pub mod page;
+pub mod updates;
+pub mod admin;
\ No newline at end of file
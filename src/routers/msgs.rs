@@ -1,24 +1,127 @@
 use std::sync::Arc;
 use std::collections::HashMap;
-use std::time::{Duration, Instant};
-use axum::extract::{Query, State, ConnectInfo};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use axum::extract::{Path, Query, State, ConnectInfo};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
 use axum::{Json, Router};
 use axum::response::{IntoResponse, Response};
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::routing::{get, post};
-use serde::Deserialize;
+use futures_util::Stream;
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
 use axum::http::{StatusCode, HeaderMap};
-use tokio::sync::Mutex;
-use crate::database::{Database, ReceiveMsg};
-use crate::database::GetMsgs::{After, Before};
-use crate::integration::Integration;
+use tokio::sync::{broadcast, Mutex};
+use crate::args::WallConfig;
+use crate::database::{Database, Msg, MsgFilter, MsgMeta, ReceiveMsg};
+use crate::database::GetMsgs::After;
+use crate::integration::{Dispatcher, Integration};
+use crate::routers::page::{self, Page};
+use crate::routers::updates::Update;
+use crate::utils::token;
 
 #[derive(Deserialize)]
 struct Pagination {
-    before: Option<usize>,
+    cursor: Option<String>,
+    limit: Option<u32>,
+    author: Option<Arc<str>>,
+    since: Option<u64>,
+    until: Option<u64>,
+    contains: Option<Arc<str>>,
+}
+
+impl Pagination {
+    fn filter(&self) -> MsgFilter {
+        // `?author=` from an emptied form field means no filter, not an empty name
+        let non_empty = |value: &Option<Arc<str>>| value.clone().filter(|value| !value.is_empty());
+        MsgFilter {
+            author: non_empty(&self.author),
+            since: self.since,
+            until: self.until,
+            contains: non_empty(&self.contains),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Search {
+    q: String,
+    cursor: Option<String>,
+    limit: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct Backfill {
     after: Option<usize>,
-    limit: usize
 }
 
+#[derive(Deserialize)]
+struct DeleteMsg {
+    id: u32,
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct React {
+    id: u32,
+    emoji: Arc<str>,
+    /// takes the reaction back instead
+    #[serde(default)]
+    remove: bool,
+}
+
+#[derive(Deserialize)]
+struct EditMsg {
+    id: u32,
+    token: String,
+    content: Arc<str>,
+}
+
+/// A message with everything that answers it, replies oldest first.
+#[derive(Serialize)]
+struct ThreadNode {
+    #[serde(flatten)]
+    msg: Arc<Msg>,
+    replies: Vec<ThreadNode>,
+}
+
+impl ThreadNode {
+    /// `msgs` as returned by `Database::thread`, the first one is the root.
+    fn build(msgs: &[Arc<Msg>]) -> Option<Self> {
+        let mut replies: HashMap<u32, Vec<&Arc<Msg>>> = HashMap::new();
+        for msg in msgs.iter().skip(1) {
+            if let Some(parent) = msg.reply_to {
+                replies.entry(parent).or_default().push(msg);
+            }
+        }
+        Some(Self::node(msgs.first()?, &replies))
+    }
+
+    fn node(msg: &Arc<Msg>, replies: &HashMap<u32, Vec<&Arc<Msg>>>) -> Self {
+        Self {
+            // deleted ones stay, or their replies would hang in the air
+            msg: match msg.deleted_at {
+                Some(_) => Arc::new(msg.tombstone()),
+                None => msg.clone(),
+            },
+            replies: replies.get(&msg.id)
+                .into_iter()
+                .flatten()
+                .map(|reply| Self::node(reply, replies))
+                .collect(),
+        }
+    }
+}
+
+// what people may react with, so reactions can't be abused as tiny messages
+const REACTIONS: &[&str] = &["👍", "👎", "❤️", "😂", "😮", "😢", "🔥"];
+// how many stored messages a (re)connecting client is caught up on per query
+const BACKFILL_LIMIT: u32 = 100;
+// and how many it may have missed at most, anyone further behind has to reload
+const BACKFILL_MAX: usize = 1000;
+// for how long after posting the author may still edit a message
+const EDIT_WINDOW_SECS: u64 = 5 * 60;
+
 struct RateLimiter {
     requests: HashMap<String, Vec<Instant>>,
     max_requests: usize,
@@ -55,14 +158,31 @@ impl RateLimiter {
 }
 
 #[derive(Clone)]
-struct AppState<T: Database> {
-    db: Arc<T>,
+struct AppState {
+    /// the one wall this router serves
+    wall: Arc<str>,
+    db: Arc<dyn Database>,
     rate_limiter: Arc<Mutex<RateLimiter>>,
+    // apart from messages, reacting shouldn't cost a message
+    reaction_limiter: Arc<Mutex<RateLimiter>>,
     integrations: Arc<[Arc<dyn Integration>]>,
+    dispatcher: Dispatcher,
+    /// the channel of this wall alone
+    updates: broadcast::Sender<Update>,
+    /// longest ttl a message may ask for, 0 if ephemeral ones aren't allowed
+    max_ttl_secs: u64,
+}
+
+impl AppState {
+    fn publish(&self, update: Update) {
+        // nobody listening is fine, so the error is ignored
+        let _ = self.updates.send(update);
+    }
 }
 
 
-fn get_client_ip(headers: &HeaderMap, conn_info: Option<&ConnectInfo<std::net::SocketAddr>>) -> String {
+#[allow(clippy::collapsible_if)]
+pub fn get_client_ip(headers: &HeaderMap, conn_info: Option<&ConnectInfo<std::net::SocketAddr>>) -> String {
     // there exists obvious abuse, when service is not behind proxy, one can send fake ip, but 
     // I will use proxy, so good luck with that 
     if let Some(forwarded_for) = headers.get("X-Forwarded-For") {
@@ -86,11 +206,11 @@ fn get_client_ip(headers: &HeaderMap, conn_info: Option<&ConnectInfo<std::net::S
     "unknown".to_string()
 }
 
-async fn send_msg<T: Database>(
-    State(state): State<Arc<AppState<T>>>,
+async fn send_msg(
+    State(state): State<Arc<AppState>>,
     headers: HeaderMap,
     ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
-    Json(msg): Json<ReceiveMsg>
+    Json(mut msg): Json<ReceiveMsg>
 ) -> Response {
     let client_ip = get_client_ip(&headers, Some(&ConnectInfo(addr)));
     tracing::info!("Request from IP: {}", client_ip);
@@ -100,93 +220,676 @@ async fn send_msg<T: Database>(
         return (StatusCode::BAD_REQUEST,
                 serde_json::json!({"err": e.to_string()}).to_string()).into_response();
     }
+    if let Some(ttl) = msg.ttl {
+        let err = match state.max_ttl_secs {
+            0 => Some("Ephemeral messages are off on this wall".to_string()),
+            max if ttl == 0 || ttl > max => Some(format!("TTL has to be between 1 and {} seconds", max)),
+            _ => None,
+        };
+        if let Some(err) = err {
+            return (StatusCode::BAD_REQUEST,
+                    serde_json::json!({"err": err}).to_string()).into_response();
+        }
+    }
     
+    if let Err(banned) = check_banned(&state, &client_ip).await {
+        return banned;
+    }
+    if let Err(limited) = check_rate_limit(&state.rate_limiter, &client_ip).await {
+        return limited;
+    }
+    if let Some(reply_to) = msg.reply_to {
+        match state.db.get_msg(reply_to).await {
+            Ok(Some(parent)) if parent.wall == state.wall => {},
+            Ok(_) => return (StatusCode::BAD_REQUEST,
+                    serde_json::json!({"err": "No message to reply to"}).to_string()).into_response(),
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR,
+                    serde_json::json!({"err": e.to_string()}).to_string()).into_response(),
+        }
+    }
+    
+    // only the author gets the token, the wall keeps its hash
+    let delete_token = token::generate();
+    msg.wall = state.wall.clone();
+    msg.meta = MsgMeta {
+        delete_token_hash: Some(Arc::from(token::hash(&delete_token))),
+        client_ip: Some(Arc::from(client_ip)),
+    };
+    // into the outbox along with the message, so a restart can't lose the posts
+    msg.deliver_to = state.integrations.iter().map(|integration| Arc::from(integration.name())).collect();
+
+    match state.db.send_msg(msg).await {
+        Ok(stored) => {
+            let id = stored.id;
+            state.dispatcher.integrate(&state.integrations, &stored);
+            state.publish(Update::Msg(stored));
+            (StatusCode::OK,
+                   serde_json::json!({"msg": "ok", "id": id, "delete_token": delete_token}).to_string())
+            .into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+                   serde_json::json!({"err": e.to_string()}).to_string())
+            .into_response()
+    }
+}
+
+async fn delete_msg(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
+    Json(DeleteMsg { id, token }): Json<DeleteMsg>
+) -> Response {
+    let client_ip = get_client_ip(&headers, Some(&ConnectInfo(addr)));
+    tracing::info!("delete_msg: {} from IP: {}", id, client_ip);
+
+    if let Err(banned) = check_banned(&state, &client_ip).await {
+        return banned;
+    }
+    if let Err(limited) = check_rate_limit(&state.rate_limiter, &client_ip).await {
+        return limited;
+    }
+    if let Err(missing) = check_on_wall(&state, id).await {
+        return missing;
+    }
+    if let Err(denied) = check_delete_token(&state, id, &token).await {
+        return denied;
+    }
+
+    match state.db.delete_msg(id).await {
+        Ok(true) => {
+            state.publish(Update::Delete { id, wall: state.wall.clone() });
+            (StatusCode::OK,
+                   serde_json::json!({"msg": "ok"}).to_string())
+            .into_response()
+        },
+        Ok(false) => (StatusCode::NOT_FOUND,
+                   serde_json::json!({"err": "No such message"}).to_string())
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+                   serde_json::json!({"err": e.to_string()}).to_string())
+            .into_response()
+    }
+}
+
+async fn edit_msg(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
+    Json(EditMsg { id, token, content }): Json<EditMsg>
+) -> Response {
+    let client_ip = get_client_ip(&headers, Some(&ConnectInfo(addr)));
+    tracing::info!("edit_msg: {} from IP: {}", id, client_ip);
+
+    if let Err(banned) = check_banned(&state, &client_ip).await {
+        return banned;
+    }
+    if let Err(limited) = check_rate_limit(&state.rate_limiter, &client_ip).await {
+        return limited;
+    }
+    let msg = match check_on_wall(&state, id).await {
+        Ok(msg) => msg,
+        Err(missing) => return missing,
+    };
+    if let Err(denied) = check_delete_token(&state, id, &token).await {
+        return denied;
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    if now.saturating_sub(msg.timestamp) > EDIT_WINDOW_SECS {
+        return (StatusCode::FORBIDDEN,
+                serde_json::json!({"err": "Too late to edit this message"}).to_string()).into_response();
+    }
+    let edited = ReceiveMsg {
+        wall: msg.wall.clone(),
+        author: msg.author.clone(),
+        content,
+        reply_to: None,
+        ttl: None,
+        deliver_to: Vec::new(),
+        meta: MsgMeta::default(),
+    };
+    if let Err(e) = edited.check_valid() {
+        return (StatusCode::BAD_REQUEST,
+                serde_json::json!({"err": e.to_string()}).to_string()).into_response();
+    }
+
+    match state.db.edit_msg(id, edited.content).await {
+        Ok(Some(msg)) => {
+            state.publish(Update::Edit(msg));
+            (StatusCode::OK,
+                   serde_json::json!({"msg": "ok"}).to_string())
+            .into_response()
+        },
+        Ok(None) => (StatusCode::NOT_FOUND,
+                   serde_json::json!({"err": "No such message"}).to_string())
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+                   serde_json::json!({"err": e.to_string()}).to_string())
+            .into_response()
+    }
+}
+
+async fn react(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
+    Json(React { id, emoji, remove }): Json<React>
+) -> Response {
+    let client_ip = get_client_ip(&headers, Some(&ConnectInfo(addr)));
+    tracing::info!("react: {} {} on {} from IP: {}", if remove { "-" } else { "+" }, emoji, id, client_ip);
+
+    if !REACTIONS.contains(&emoji.as_ref()) {
+        return (StatusCode::BAD_REQUEST,
+                serde_json::json!({"err": "Unknown reaction"}).to_string()).into_response();
+    }
+    if let Err(banned) = check_banned(&state, &client_ip).await {
+        return banned;
+    }
+    if let Err(limited) = check_rate_limit(&state.reaction_limiter, &client_ip).await {
+        return limited;
+    }
+    if let Err(missing) = check_on_wall(&state, id).await {
+        return missing;
+    }
+
+    match state.db.react(id, &emoji, &client_ip, !remove).await {
+        Ok(Some(msg)) => {
+            state.publish(Update::Reactions { id, reactions: msg.reactions.clone(), wall: state.wall.clone() });
+            (StatusCode::OK,
+                   serde_json::json!({"msg": "ok", "reactions": msg.reactions}).to_string())
+            .into_response()
+        },
+        Ok(None) => (StatusCode::NOT_FOUND,
+                   serde_json::json!({"err": "No such message"}).to_string())
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+                   serde_json::json!({"err": e.to_string()}).to_string())
+            .into_response()
+    }
+}
+
+/// Live message `id`, as long as it is on this router's wall.
+async fn check_on_wall(state: &AppState, id: u32) -> Result<Arc<Msg>, Response> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    match state.db.get_msg(id).await {
+        // run out but not swept yet is as good as gone
+        Ok(Some(msg)) if msg.wall == state.wall && !msg.is_expired(now) => Ok(msg),
+        Ok(_) => Err((StatusCode::NOT_FOUND,
+                   serde_json::json!({"err": "No such message"}).to_string())
+            .into_response()),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR,
+                   serde_json::json!({"err": e.to_string()}).to_string())
+            .into_response()),
+    }
+}
+
+/// Lets through only whoever holds the token handed out when message `id` was sent.
+async fn check_delete_token(state: &AppState, id: u32, token: &str) -> Result<(), Response> {
+    match state.db.delete_token_hash(id).await {
+        Ok(Some(hash)) if *hash == token::hash(token) => Ok(()),
+        Ok(Some(_)) => Err((StatusCode::FORBIDDEN,
+                   serde_json::json!({"err": "Wrong delete token"}).to_string())
+            .into_response()),
+        Ok(None) => Err((StatusCode::NOT_FOUND,
+                   serde_json::json!({"err": "No such message"}).to_string())
+            .into_response()),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR,
+                   serde_json::json!({"err": e.to_string()}).to_string())
+            .into_response()),
+    }
+}
+
+async fn check_banned(state: &AppState, client_ip: &str) -> Result<(), Response> {
+    match state.db.is_banned(client_ip).await {
+        Ok(false) => Ok(()),
+        Ok(true) => {
+            tracing::warn!("Banned IP: {}", client_ip);
+            Err((StatusCode::FORBIDDEN,
+                   serde_json::json!({"err": "You are banned"}).to_string())
+                .into_response())
+        },
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR,
+                   serde_json::json!({"err": e.to_string()}).to_string())
+            .into_response()),
+    }
+}
+
+async fn check_rate_limit(limiter: &Mutex<RateLimiter>, client_ip: &str) -> Result<(), Response> {
     let is_limited = {
-        let mut rate_limiter = state.rate_limiter.lock().await;
-        rate_limiter.check_request_limit(&client_ip)
+        let mut rate_limiter = limiter.lock().await;
+        rate_limiter.check_request_limit(client_ip)
     };
 
     if is_limited {
         tracing::warn!("Rate limit exceeded for IP: {}", client_ip);
-        return (
+        return Err((
             StatusCode::TOO_MANY_REQUESTS,
             serde_json::json!({
                 "error": "Превышен лимит сообщений. Попробуй через минутку (и прекрати спамить)",
             }).to_string()
-        ).into_response();
+        ).into_response());
     }
-    
-    let db = state.db.clone();
-    let cloned_msg = msg.clone();
-    match db.send_msg(msg).await {
-        Ok(()) => {
-            for integration in state.integrations.iter() {
-                std::thread::spawn(integration.integrate(cloned_msg.clone()));
-            }
-            (StatusCode::OK,
-                   serde_json::json!({"msg": "ok"}).to_string())
-            .into_response()
+    Ok(())
+}
+
+async fn get_msgs(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<Pagination>
+) -> Response {
+    let count = match page::decode(query.cursor.as_deref()) {
+        Ok(count) => count,
+        Err(e) => return (StatusCode::BAD_REQUEST,
+                serde_json::json!({"err": e.to_string()}).to_string()).into_response(),
+    };
+    let limit = page::limit(query.limit);
+
+    match state.db.get_msgs(&state.wall, count.clone(), &query.filter(), limit + 1).await {
+        Ok(msgs) => {
+            tracing::info!("get_msgs: {:?}", msgs);
+            (StatusCode::OK, Json(Page::new(msgs, &count, limit, |msg| msg.id))).into_response()
         },
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+        Err(e) => {
+            tracing::error!("get_msgs error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR,
                    serde_json::json!({"err": e.to_string()}).to_string())
-            .into_response()
+                .into_response()
+        }
     }
 }
 
-async fn get_msgs<T: Database>(
-    State(state): State<Arc<AppState<T>>>,
-    query: Query<Pagination>
+async fn search(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<Search>
 ) -> Response {
-    let db = state.db.clone();
-    if let Some(before) = query.before {
-        if let Some(_) = query.after {
-            tracing::error!("before and after can't be set at the same time");
-            (StatusCode::BAD_REQUEST, "before and after can't be set at the same time")
-                    .into_response()
-        } else {
-            if let Ok(msgs) = db.get_msgs(Before(before), query.limit as u32).await {
-                tracing::info!("get_msgs: {:?}", msgs);
-                (StatusCode::OK, Json(msgs)).into_response()
-            } else {
-                tracing::error!("get_msgs error");
-                (StatusCode::INTERNAL_SERVER_ERROR, "error").into_response()
-            }
+    let count = match page::decode(query.cursor.as_deref()) {
+        Ok(count) => count,
+        Err(e) => return (StatusCode::BAD_REQUEST,
+                serde_json::json!({"err": e.to_string()}).to_string()).into_response(),
+    };
+    let limit = page::limit(query.limit);
+    if query.q.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST,
+                serde_json::json!({"err": "Nothing to search for"}).to_string()).into_response();
+    }
+
+    match state.db.search(&state.wall, &query.q, count.clone(), limit + 1).await {
+        Ok(hits) => {
+            tracing::info!("search {:?}: {} hits", query.q, hits.len());
+            (StatusCode::OK, Json(Page::new(hits, &count, limit, |hit| hit.msg.id))).into_response()
+        },
+        Err(e) => {
+            tracing::error!("search error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR,
+                   serde_json::json!({"err": e.to_string()}).to_string())
+                .into_response()
         }
-    } else {
-        if let Some(after) = query.after {
-            if let Ok(msgs) = db.get_msgs(After(after), query.limit as u32).await {
-                tracing::info!("get_msgs: {:?}", msgs);
-                (StatusCode::OK, Json(msgs)).into_response()
-            } else {
-                tracing::error!("get_msgs error");
-                (StatusCode::INTERNAL_SERVER_ERROR, "error").into_response()
-            }
-        } else {
-            tracing::error!("before or after must be set");
-            (StatusCode::BAD_REQUEST, "before or after must be set").into_response()
+    }
+}
+
+async fn thread(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u32>,
+) -> Response {
+    match state.db.thread(id).await {
+        // replies always share the wall of what they answer, so checking the root is enough
+        Ok(msgs) if msgs.first().is_some_and(|root| root.wall != state.wall) => (StatusCode::NOT_FOUND,
+                   serde_json::json!({"err": "No such message"}).to_string())
+            .into_response(),
+        Ok(msgs) => match ThreadNode::build(&msgs) {
+            Some(thread) => (StatusCode::OK, Json(thread)).into_response(),
+            None => (StatusCode::NOT_FOUND,
+                   serde_json::json!({"err": "No such message"}).to_string())
+                .into_response(),
+        },
+        Err(e) => {
+            tracing::error!("thread error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR,
+                   serde_json::json!({"err": e.to_string()}).to_string())
+                .into_response()
+        }
+    }
+}
+
+async fn pinned(
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    match state.db.pinned(&state.wall).await {
+        Ok(msgs) => (StatusCode::OK, Json(msgs)).into_response(),
+        Err(e) => {
+            tracing::error!("pinned error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR,
+                   serde_json::json!({"err": e.to_string()}).to_string())
+                .into_response()
         }
     }
 }
 
-async fn last_msg<T: Database>(
-    State(state): State<Arc<AppState<T>>>,
+async fn last_msg(
+    State(state): State<Arc<AppState>>,
 ) -> Response {
     let db = state.db.clone();
-    let last_id = db.last_msg().await.unwrap_or(0);
+    let last_id = db.last_msg(&state.wall).await.unwrap_or(0);
     tracing::info!("last_id: {}", last_id);
     serde_json::json!({"id": last_id}).to_string().into_response()
 }
 
+async fn ws(
+    State(state): State<Arc<AppState>>,
+    Query(backfill): Query<Backfill>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    upgrade.on_upgrade(move |socket| push_msgs(socket, state, backfill.after))
+}
+
+async fn push_msgs(mut socket: WebSocket, state: Arc<AppState>, after: Option<usize>) {
+    // subscribe before backfilling, so messages stored in between aren't lost
+    let mut updates = state.updates.subscribe();
+    let mut last_sent = 0;
 
-pub fn msgs<T: Database>(db: T, integrations: Arc<[Arc<dyn Integration>]>) -> Router {
+    let msgs = match backlog(state.db.as_ref(), &state.wall, after).await {
+        Ok(Backlog::Msgs(msgs)) => msgs,
+        Ok(Backlog::Reload) => {
+            let _ = send_reload(&mut socket).await;
+            return;
+        },
+        // closing lets the client reconnect after its usual delay, not reload right away
+        Err(e) => {
+            tracing::error!("backlog error: {}", e);
+            return;
+        },
+    };
+    for msg in msgs {
+        last_sent = msg.id;
+        if send_json(&mut socket, &Update::Msg(msg)).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        tokio::select! {
+            received = updates.recv() => match received {
+                Ok(Update::Msg(msg)) if msg.id <= last_sent => continue,
+                Ok(update) => {
+                    if let Update::Msg(msg) = &update {
+                        last_sent = msg.id;
+                    }
+                    if send_json(&mut socket, &update).await.is_err() {
+                        return;
+                    }
+                },
+                // whatever it missed can't be told apart any more, so it starts over
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("ws client lagged behind, skipped {} updates", skipped);
+                    let _ = send_reload(&mut socket).await;
+                    return;
+                },
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {},
+            },
+        }
+    }
+}
+
+async fn events(
+    State(state): State<Arc<AppState>>,
+    Query(backfill): Query<Backfill>,
+    headers: HeaderMap,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let updates = state.updates.subscribe();
+    // a reconnecting EventSource sends the id of the last event it saw
+    let after = headers.get("Last-Event-ID")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.trim().parse().ok())
+        .or(backfill.after);
+    let (backlog, updates, last_sent) = match backlog(state.db.as_ref(), &state.wall, after).await {
+        Ok(Backlog::Msgs(msgs)) => {
+            let last_sent = msgs.last().map_or(0, |msg| msg.id);
+            (msgs.iter().map(|msg| msg_event(msg)).collect(), Some(updates), last_sent)
+        },
+        // nothing follows, the client has to start over
+        Ok(Backlog::Reload) => (vec![reload_event()], None, 0),
+        // an empty stream, the EventSource reconnects after its usual delay
+        Err(e) => {
+            tracing::error!("backlog error: {}", e);
+            (Vec::new(), None, 0)
+        },
+    };
+
+    let events = stream::unfold((backlog.into_iter(), updates, last_sent), move |(mut backlog, updates, last_sent)| {
+        async move {
+            if let Some(event) = backlog.next() {
+                return Some((event, (backlog, updates, last_sent)));
+            }
+            let mut updates = updates?;
+            loop {
+                match updates.recv().await {
+                    Ok(Update::Msg(msg)) if msg.id > last_sent => {
+                        let id = msg.id;
+                        return Some((msg_event(&msg), (backlog, Some(updates), id)));
+                    },
+                    Ok(Update::Msg(_)) => continue,
+                    // no ids, so Last-Event-ID keeps pointing at the last new message
+                    Ok(Update::Edit(msg)) => {
+                        let event = Event::default()
+                            .event("edit")
+                            .json_data(&msg);
+                        return Some((event, (backlog, Some(updates), last_sent)));
+                    },
+                    Ok(Update::Delete { id, .. }) => {
+                        let event = Event::default()
+                            .event("delete")
+                            .json_data(serde_json::json!({"id": id}));
+                        return Some((event, (backlog, Some(updates), last_sent)));
+                    },
+                    Ok(Update::Reactions { id, reactions, .. }) => {
+                        let event = Event::default()
+                            .event("reactions")
+                            .json_data(serde_json::json!({"id": id, "reactions": reactions}));
+                        return Some((event, (backlog, Some(updates), last_sent)));
+                    },
+                    // whatever it missed can't be told apart any more, so it starts over
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("sse client lagged behind, skipped {} updates", skipped);
+                        return Some((reload_event(), (backlog, None, last_sent)));
+                    },
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        }
+    });
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+fn msg_event(msg: &Msg) -> Result<Event, axum::Error> {
+    Event::default()
+        .id(msg.id.to_string())
+        .json_data(msg)
+}
+
+/// The SSE counterpart of `send_reload`, browsers skip events without data.
+fn reload_event() -> Result<Event, axum::Error> {
+    Ok(Event::default().event("reload").data("{}"))
+}
+
+/// What a (re)connecting client missed.
+enum Backlog {
+    /// every stored message after its cursor, oldest first
+    Msgs(Vec<Arc<Msg>>),
+    /// more than it can be caught up on, it has to start over
+    Reload,
+}
+
+async fn backlog(db: &dyn Database, wall: &str, after: Option<usize>) -> anyhow::Result<Backlog> {
+    let Some(mut after) = after else {
+        return Ok(Backlog::Msgs(Vec::new()));
+    };
+    let mut msgs = Vec::new();
+    loop {
+        let page = db.get_msgs(wall, After(after), &MsgFilter::default(), BACKFILL_LIMIT).await?;
+        if msgs.len() + page.len() > BACKFILL_MAX {
+            return Ok(Backlog::Reload);
+        }
+        let caught_up = page.len() < BACKFILL_LIMIT as usize;
+        if let Some(newest) = page.first() {
+            after = newest.id as usize;
+        }
+        msgs.extend(page.into_iter().rev());
+        if caught_up {
+            return Ok(Backlog::Msgs(msgs));
+        }
+    }
+}
+
+async fn send_json(socket: &mut WebSocket, update: &Update) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(update).map_err(axum::Error::new)?;
+    socket.send(Message::Text(text.into())).await
+}
+
+/// Tells a client it missed updates it can't be sent, so it should fetch the wall anew.
+async fn send_reload(socket: &mut WebSocket) -> Result<(), axum::Error> {
+    socket.send(Message::Text(serde_json::json!({"type": "reload"}).to_string().into())).await
+}
+
+/// Everything clients do on one wall, a router per wall.
+pub fn msgs(
+    db: Arc<dyn Database>,
+    wall: &WallConfig,
+    integrations: Arc<[Arc<dyn Integration>]>,
+    dispatcher: Dispatcher,
+    updates: broadcast::Sender<Update>,
+) -> Router {
     let state = AppState {
-        db: Arc::new(db),
-        rate_limiter: Arc::new(Mutex::new(RateLimiter::new(2, 60))),
+        wall: Arc::from(wall.name.as_str()),
+        db,
+        rate_limiter: Arc::new(Mutex::new(RateLimiter::new(wall.rate_limit, wall.rate_window_secs))),
+        reaction_limiter: Arc::new(Mutex::new(RateLimiter::new(20, 60))),
         integrations,
+        dispatcher,
+        updates,
+        max_ttl_secs: wall.max_ttl_secs,
     };
     Router::new()
         .route("/get_msgs", get(get_msgs))
         .route("/send_msg", post(send_msg))
+        .route("/delete_msg", post(delete_msg))
+        .route("/edit_msg", post(edit_msg))
+        .route("/react", post(react))
+        .route("/search", get(search))
+        .route("/thread/{id}", get(thread))
+        .route("/pinned", get(pinned))
         .route("/last_msg", get(last_msg))
+        .route("/ws", get(ws))
+        .route("/events", get(events))
         .with_state(Arc::new(state))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use crate::database::DEFAULT_WALL;
+    use crate::database::mock::MockBase;
+    use crate::integration::DispatcherConfig;
+    use crate::routers::updates::Updates;
+    use super::*;
+
+    async fn wall_of(db: &dyn Database, count: usize) {
+        for i in 1..=count {
+            db.send_msg(ReceiveMsg {
+                wall: Arc::from(DEFAULT_WALL),
+                author: Arc::from("author"),
+                content: Arc::from(format!("msg {}", i)),
+                reply_to: None,
+                ttl: None,
+                deliver_to: Vec::new(),
+                meta: MsgMeta::default(),
+            }).await.unwrap();
+        }
+    }
+
+    async fn backlog_ids(db: &dyn Database, after: Option<usize>) -> Option<Vec<u32>> {
+        match backlog(db, DEFAULT_WALL, after).await.unwrap() {
+            Backlog::Msgs(msgs) => Some(msgs.iter().map(|msg| msg.id).collect()),
+            Backlog::Reload => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn backlog_catches_up_on_everything_missed() {
+        let db = MockBase::new();
+        wall_of(&db, 2 * BACKFILL_LIMIT as usize + 50).await;
+        let missed = backlog_ids(&db, Some(10)).await.unwrap();
+        assert_eq!(missed, (11..=2 * BACKFILL_LIMIT + 50).collect::<Vec<_>>());
+        assert_eq!(backlog_ids(&db, Some(2 * BACKFILL_LIMIT as usize + 50)).await.unwrap(), Vec::<u32>::new());
+        assert_eq!(backlog_ids(&db, None).await.unwrap(), Vec::<u32>::new());
+    }
+
+    #[tokio::test]
+    async fn backlog_too_far_behind_reloads() {
+        let db = MockBase::new();
+        wall_of(&db, BACKFILL_MAX + 1).await;
+        assert!(backlog_ids(&db, Some(0)).await.is_none());
+        assert_eq!(backlog_ids(&db, Some(1)).await.unwrap().len(), BACKFILL_MAX);
+    }
+
+    /// The wall's router on a local port, with nothing to forward to.
+    async fn serve(db: Arc<dyn Database>) -> String {
+        let wall: WallConfig = serde_json::from_value(serde_json::json!({"name": DEFAULT_WALL})).unwrap();
+        let config = DispatcherConfig { workers: 1, queue_capacity: 8, max_attempts: 1 };
+        let dispatcher = Dispatcher::start(&config, db.clone());
+        let updates = Updates::new([DEFAULT_WALL]);
+        let app = msgs(db, &wall, Arc::from([]), dispatcher, updates.wall(DEFAULT_WALL).unwrap());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        url
+    }
+
+    /// The `event` and `id` of the first `count` events `/events` sends after `last_event_id`.
+    async fn events_after(url: &str, last_event_id: usize, count: usize) -> Vec<(String, Option<u32>)> {
+        let url = format!("{}/events", url);
+        tokio::task::spawn_blocking(move || {
+            let response = ureq::get(&url)
+                .header("Last-Event-ID", &last_event_id.to_string())
+                .call()
+                .unwrap();
+            let mut events = Vec::new();
+            let (mut event, mut id) = ("message".to_string(), None);
+            for line in BufReader::new(response.into_body().into_reader()).lines() {
+                let line = line.unwrap();
+                if let Some(name) = line.strip_prefix("event: ") {
+                    event = name.to_string();
+                } else if let Some(value) = line.strip_prefix("id: ") {
+                    id = Some(value.parse().unwrap());
+                } else if line.is_empty() {
+                    events.push((std::mem::replace(&mut event, "message".to_string()), id.take()));
+                    if events.len() == count {
+                        break;
+                    }
+                }
+            }
+            events
+        }).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn events_resume_after_the_last_seen_one() {
+        let db: Arc<dyn Database> = Arc::new(MockBase::new());
+        wall_of(db.as_ref(), 2 * BACKFILL_LIMIT as usize + 50).await;
+        let url = serve(db).await;
+        let events = events_after(&url, 10, 2 * BACKFILL_LIMIT as usize + 40).await;
+        let expected: Vec<_> = (11..=2 * BACKFILL_LIMIT + 50).map(|id| ("message".to_string(), Some(id))).collect();
+        assert_eq!(events, expected);
+    }
+
+    #[tokio::test]
+    async fn events_too_far_behind_reload() {
+        let db: Arc<dyn Database> = Arc::new(MockBase::new());
+        wall_of(db.as_ref(), BACKFILL_MAX + 1).await;
+        let url = serve(db).await;
+        // the stream ends after it, so asking for more gets just the one
+        assert_eq!(events_after(&url, 0, 2).await, [("reload".to_string(), None)]);
+    }
+}
@@ -0,0 +1,88 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use crate::database::GetMsgs;
+use crate::database::GetMsgs::{After, Before, Latest};
+
+// what a page holds unless the client asks otherwise
+const DEFAULT_LIMIT: u32 = 20;
+// and the most it may ask for
+const MAX_LIMIT: u32 = 100;
+
+/// One page of a listing, items newest first. A page goes one way from its cursor:
+/// back to older items from none or a `Before` cursor, forward to newer ones from an `After` one.
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// older items, only set when going back and there are some
+    pub next_cursor: Option<String>,
+    /// items newer than these, to poll for what came after
+    pub prev_cursor: Option<String>,
+    /// whether more items lie the way the page went
+    pub has_more: bool,
+}
+
+impl<T> Page<T> {
+    /// `items` must have been fetched with a limit of `limit + 1`, the extra one
+    /// only tells whether more items remain. `id` gives an item's message id.
+    pub fn new(mut items: Vec<T>, count: &GetMsgs, limit: u32, id: impl Fn(&T) -> u32) -> Self {
+        let has_more = items.len() > limit as usize;
+        // items may be in any order (search ranks them), the extra one is always
+        // the furthest from the cursor
+        let furthest = match count {
+            After(_) => (0..items.len()).max_by_key(|&i| id(&items[i])),
+            Latest | Before(_) => (0..items.len()).min_by_key(|&i| id(&items[i])),
+        };
+        if has_more && let Some(furthest) = furthest {
+            items.remove(furthest);
+        }
+
+        let oldest = items.iter().map(&id).min();
+        let newest = items.iter().map(&id).max();
+        let prev = match (newest, count) {
+            (Some(newest), _) => Some(newest as usize),
+            // nothing newer yet, the same cursor is where to look again
+            (None, After(after)) => Some(*after),
+            (None, Latest) => Some(0),
+            (None, Before(_)) => None,
+        };
+        let back = !matches!(count, After(_));
+        Self {
+            items,
+            next_cursor: oldest.filter(|_| back && has_more).map(|oldest| encode(&Before(oldest as usize))),
+            prev_cursor: prev.map(|newest| encode(&After(newest))),
+            has_more,
+        }
+    }
+}
+
+/// Clients only ever pass cursors back, so what they are made of stays ours to change.
+pub fn encode(count: &GetMsgs) -> String {
+    let plain = match count {
+        // the same as no cursor
+        Latest => String::new(),
+        After(after) => format!("a{}", after),
+        Before(before) => format!("b{}", before),
+    };
+    hex::encode(plain)
+}
+
+/// No cursor means the latest page.
+pub fn decode(cursor: Option<&str>) -> Result<GetMsgs> {
+    let Some(cursor) = cursor.filter(|cursor| !cursor.is_empty()) else {
+        return Ok(Latest);
+    };
+    let invalid = || anyhow!("Invalid cursor");
+    let plain = String::from_utf8(hex::decode(cursor).map_err(|_| invalid())?).map_err(|_| invalid())?;
+    let (kind, id) = plain.split_at_checked(1).ok_or_else(invalid)?;
+    let id = id.parse().map_err(|_| invalid())?;
+    match kind {
+        "a" => Ok(After(id)),
+        "b" => Ok(Before(id)),
+        _ => Err(invalid()),
+    }
+}
+
+/// The requested page size, kept within what the server allows.
+pub fn limit(requested: Option<u32>) -> u32 {
+    requested.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+}
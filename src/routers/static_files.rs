@@ -2,16 +2,22 @@ use axum::Router;
 use axum::routing::get_service;
 use tower_http::services::ServeFile;
 
-pub fn static_paths() -> Router {
+/// The page is the same for every wall, it finds out which one it is on from the url.
+pub fn static_paths(walls: &[&str]) -> Router {
     let app_js = get_service(ServeFile::new("./public/app.js"));
     let styles_css = get_service(ServeFile::new("./public/styles.css"));
 
     let index = get_service(ServeFile::new("./public/index.html"));
     let not_found = get_service(ServeFile::new("./public/404.html"));
 
-    Router::new()
-        .route("/", index)
+    let mut router = Router::new()
+        .route("/", index.clone())
         .route("/styles.css", styles_css)
-        .route("/app.js", app_js)
-        .fallback(not_found)
+        .route("/app.js", app_js);
+    for wall in walls {
+        router = router
+            .route(&format!("/w/{}", wall), index.clone())
+            .route(&format!("/w/{}/", wall), index.clone());
+    }
+    router.fallback(not_found)
 }
\ No newline at end of file
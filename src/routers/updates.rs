@@ -0,0 +1,87 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use crate::database::Msg;
+
+/// What live clients get told about.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Update {
+    Msg(Arc<Msg>),
+    Edit(Arc<Msg>),
+    // clients only ever get updates of their own wall, so they aren't told which
+    Delete {
+        id: u32,
+        #[serde(skip)]
+        wall: Arc<str>,
+    },
+    Reactions {
+        id: u32,
+        reactions: BTreeMap<Arc<str>, u32>,
+        #[serde(skip)]
+        wall: Arc<str>,
+    },
+}
+
+impl Update {
+    pub fn wall(&self) -> &str {
+        match self {
+            Update::Msg(msg) | Update::Edit(msg) => &msg.wall,
+            Update::Delete { wall, .. } | Update::Reactions { wall, .. } => wall,
+        }
+    }
+}
+
+// how many updates of its wall a slow client may fall behind before it misses some
+const UPDATES_CAPACITY: usize = 64;
+
+/// A channel per wall, so a busy wall can't push the clients of a quiet one behind.
+/// Shared by everything that changes walls, so all of it reaches live clients.
+#[derive(Clone)]
+pub struct Updates {
+    walls: Arc<HashMap<Arc<str>, broadcast::Sender<Update>>>,
+}
+
+impl Updates {
+    pub fn new<'a>(walls: impl IntoIterator<Item = &'a str>) -> Self {
+        let walls = walls.into_iter()
+            .map(|wall| (Arc::from(wall), broadcast::channel(UPDATES_CAPACITY).0))
+            .collect();
+        Self { walls: Arc::new(walls) }
+    }
+
+    /// The channel of `wall`, `None` if it isn't one of those served.
+    pub fn wall(&self, wall: &str) -> Option<broadcast::Sender<Update>> {
+        self.walls.get(wall).cloned()
+    }
+
+    /// Passes the update on to the live clients of its wall.
+    pub fn send(&self, update: Update) {
+        if let Some(channel) = self.walls.get(update.wall()) {
+            // nobody listening is fine, so the error is ignored
+            let _ = channel.send(update);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn walls_fall_behind_on_their_own() {
+        let updates = Updates::new(["main", "quiet"]);
+        let mut main = updates.wall("main").unwrap().subscribe();
+        let mut quiet = updates.wall("quiet").unwrap().subscribe();
+        for id in 0..2 * UPDATES_CAPACITY as u32 {
+            updates.send(Update::Delete { id, wall: Arc::from("main") });
+        }
+        updates.send(Update::Delete { id: 1000, wall: Arc::from("quiet") });
+        updates.send(Update::Delete { id: 1001, wall: Arc::from("gone") });
+
+        assert!(matches!(main.recv().await, Err(broadcast::error::RecvError::Lagged(_))));
+        assert!(matches!(quiet.recv().await, Ok(Update::Delete { id: 1000, .. })));
+        assert!(quiet.try_recv().is_err());
+    }
+}
@@ -0,0 +1,46 @@
+//! Removes ephemeral messages once they run out, telling live clients
+//! and taking them back from integrations that can.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::database::Database;
+use crate::integration::{Dispatcher, Integration};
+use crate::routers::updates::{Update, Updates};
+
+/// Sweeps every `interval_secs` for as long as the server runs,
+/// `integrations` are those of each wall by its name.
+pub async fn run(
+    db: Arc<dyn Database>,
+    integrations: HashMap<String, Arc<[Arc<dyn Integration>]>>,
+    dispatcher: Dispatcher,
+    updates: Updates,
+    interval_secs: u64,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let expired = match db.remove_expired(now).await {
+            Ok(expired) => expired,
+            Err(e) => {
+                tracing::error!("Sweeping expired messages failed: {}", e);
+                continue;
+            },
+        };
+        if expired.is_empty() {
+            continue;
+        }
+        tracing::info!("Swept {} expired messages", expired.len());
+        for msg in expired {
+            if let Some(integrations) = integrations.get(msg.wall.as_ref()) {
+                dispatcher.retract(integrations, &msg);
+            }
+            // clients already dropped the deleted ones
+            if msg.deleted_at.is_none() {
+                updates.send(Update::Delete { id: msg.id, wall: msg.wall.clone() });
+            }
+        }
+    }
+}
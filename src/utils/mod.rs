@@ -1 +1,3 @@
-pub mod html;
\ No newline at end of file
+pub mod html;
+pub mod markdown;
+pub mod token;
\ No newline at end of file
@@ -0,0 +1,11 @@
+use sha2::{Digest, Sha256};
+
+/// Fresh random secret to hand out, 256 bits as hex.
+pub fn generate() -> String {
+    hex::encode(rand::random::<[u8; 32]>())
+}
+
+/// What gets stored instead of the secret itself.
+pub fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}